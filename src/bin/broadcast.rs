@@ -1,38 +1,178 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use dist_sys_rs::{
-    message::{Body, BodyKind, Message, Payload},
+    error::{self, Error},
+    log_warn,
+    message::{Body, BodyKind, Message},
     server::{HasInner, Serve, ServerInner},
+    topology::{self, Given, TopologyStrategy},
 };
-use serde_json::json;
 
+/// milliseconds between two flushes of new values to neighbors
+const FLUSH_INTERVAL_ENV: &str = "DIST_SYS_BROADCAST_FLUSH_MS";
+/// number of new values which triggers a flush before the interval is up
+const BATCH_SIZE_ENV: &str = "DIST_SYS_BROADCAST_BATCH_SIZE";
+
+/**
+ * trades latency for fewer messages between nodes, read from the env vars above
+ */
+#[derive(Debug, Clone)]
+pub struct BroadcastConfig {
+    pub flush_interval: Duration,
+    pub batch_size: usize,
+    /// every `retry_rounds`-th flush resends values still waiting for an ack
+    pub retry_rounds: usize,
+    /// turns the topology from maelstrom into the neighbors values are sent to
+    pub topology: Arc<dyn TopologyStrategy>,
+}
+
+impl Default for BroadcastConfig {
+    fn default() -> Self {
+        Self {
+            flush_interval: Duration::from_millis(100),
+            batch_size: 64,
+            retry_rounds: 5,
+            topology: Arc::new(Given),
+        }
+    }
+}
+
+impl BroadcastConfig {
+    pub fn from_env() -> Self {
+        let mut config = BroadcastConfig::default();
+        if let Some(ms) = env_usize(FLUSH_INTERVAL_ENV) {
+            config.flush_interval = Duration::from_millis(ms as u64);
+        }
+        if let Some(batch_size) = env_usize(BATCH_SIZE_ENV) {
+            config.batch_size = batch_size.max(1);
+        }
+        config.topology = topology::from_env();
+        config
+    }
+}
+
+fn env_usize(name: &str) -> Option<usize> {
+    let value = env::var(name).ok()?;
+    match value.parse() {
+        Ok(n) => Some(n),
+        Err(err) => {
+            log_warn!("", "ignore {}={}: {}", name, value, err);
+            None
+        }
+    }
+}
+
+/**
+ * every value once, in the order it was added.
+ * the version is the number of values, values added after version v are
+ * `since(v)`, which makes reads and gossip of new values cheap
+ */
 #[derive(Debug, Default)]
-pub struct BroadcastServer {
-    inner: ServerInner,
-    pub messages: Vec<usize>,
+pub struct MessageStore {
+    values: Vec<usize>,
+    index: HashSet<usize>,
+}
+
+impl MessageStore {
     /**
-     * latest idx of messages not sent to node, start from 0.
-     * each node has an idx
+     * return false if value is already stored
      */
-    sent_idx_map: HashMap<String, usize>,
-    pub topology: HashMap<String, Vec<String>>,
+    pub fn insert(&mut self, value: usize) -> bool {
+        if !self.index.insert(value) {
+            return false;
+        }
+        self.values.push(value);
+        true
+    }
+
+    pub fn contains(&self, value: &usize) -> bool {
+        self.index.contains(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn version(&self) -> usize {
+        self.values.len()
+    }
+
+    /**
+     * values added after version, all of them for version 0
+     */
+    pub fn since(&self, version: usize) -> &[usize] {
+        &self.values[version.min(self.values.len())..]
+    }
+}
+
+#[derive(Debug, Default)]
+struct Neighbor {
+    /// store version up to which values were sent to the neighbor, or are known to it
+    sent_version: usize,
+    /// values the neighbor is known to have, acknowledged by it or gossiped from it
+    known: HashSet<usize>,
+    /// values sent to the neighbor which it hasn't acknowledged yet
+    in_flight: BTreeSet<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Delivery {
+    neighbors: HashMap<String, Neighbor>,
+    /// new values since the last flush
+    unflushed: usize,
+    /// flushes done by the timer
+    round: usize,
+}
+
+/**
+ * new values are batched into one gossip per neighbor, flushed every
+ * `flush_interval` or as soon as `batch_size` of them pile up.
+ * values are resent until the neighbor acknowledges them,
+ * so they reach every node once a partition heals.
+ * locks are always taken in field order: topology, messages, delivery
+ */
+#[derive(Debug, Default)]
+pub struct BroadcastServer {
+    inner: ServerInner,
+    config: BroadcastConfig,
+    pub topology: Mutex<HashMap<String, Vec<String>>>,
+    pub messages: Mutex<MessageStore>,
+    delivery: Mutex<Delivery>,
 }
 
 impl BroadcastServer {
+    pub fn new(config: BroadcastConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
     /**
      * This message requests that a value be broadcast out to all nodes in the cluster.
      * The value is always an integer and it is unique for each message from Maelstrom.
      */
-    pub fn broadcast(&mut self, msg: &Message) -> Message {
-        self.messages.push(msg.body.payload.get_usize("message"));
+    pub fn broadcast(&self, msg: &Message, message: usize) -> Message {
+        let is_new = self.messages.lock().unwrap().insert(message);
+        if is_new {
+            self.added(1);
+        }
 
         let body = Body {
             kind: BodyKind::BroadcastOk,
-            msg_id: self.inner.next_msg_id(),
+            msg_id: self.inner.next_id(),
             reply_to: Some(msg.body.msg_id),
-            payload: Payload::default(),
         };
 
         Message {
@@ -43,48 +183,144 @@ impl BroadcastServer {
     }
 
     /**
-     * broadcast back to other nodes in this cluster
+     * count new values, flush right away once a batch is full
      */
-    pub fn broadcast_back(&mut self) -> Option<Vec<Message>> {
+    fn added(&self, count: usize) {
+        let full = {
+            let mut delivery = self.delivery.lock().unwrap();
+            delivery.unflushed += count;
+            delivery.unflushed >= self.config.batch_size
+        };
+        if full {
+            for msg in self.flush(false) {
+                self.inner.send(msg);
+            }
+        }
+    }
+
+    /**
+     * neighbors from the topology message, or every peer until it arrives
+     */
+    fn neighbors(&self, topology: &HashMap<String, Vec<String>>) -> Vec<String> {
+        match topology.get(self.inner.node_id()) {
+            Some(others) => others.clone(),
+            None => self.inner.peers().into_iter().map(str::to_string).collect(),
+        }
+    }
+
+    /**
+     * one gossip per neighbor with the values it doesn't have yet.
+     * values waiting for an ack are only sent again when `retry` is set
+     */
+    pub fn flush(&self, retry: bool) -> Vec<Message> {
         let src = self.inner.node_id();
+        let topology = self.topology.lock().unwrap();
+        let messages = self.messages.lock().unwrap();
+        let mut delivery = self.delivery.lock().unwrap();
+        delivery.unflushed = 0;
 
-        self.topology.get(src).map(|others| {
-            let mut ret = Vec::with_capacity(5 * others.len()); // TODO
-            for node_id in others.iter() {
-                let sent_idx = *self.sent_idx_map.get(node_id).unwrap_or(&0);
-                let msg_len = self.messages.len();
-                let msg_range = sent_idx..msg_len;
-
-                for msg_id in msg_range {
-                    let body = Body {
-                        kind: BodyKind::Broadcast,
-                        msg_id: self.inner.next_msg_id(),
-                        reply_to: None,
-                        payload: Payload::init("message", json!(msg_id)),
-                    };
-                    let message = Message {
-                        src: src.to_string(),
-                        dst: node_id.to_string(),
-                        body,
-                    };
-                    ret.push(message);
+        let mut ret = Vec::new();
+        for node_id in self.neighbors(&topology) {
+            let neighbor = delivery.neighbors.entry(node_id.clone()).or_default();
+            let mut values: Vec<usize> = match retry {
+                true => neighbor.in_flight.iter().copied().collect(),
+                false => Vec::new(),
+            };
+            for value in messages.since(neighbor.sent_version) {
+                if !neighbor.known.contains(value) && neighbor.in_flight.insert(*value) {
+                    values.push(*value);
                 }
-                // update idx to next
-                self.sent_idx_map.insert(node_id.to_string(), msg_len);
             }
-            ret
-        })
+            neighbor.sent_version = messages.version();
+            if values.is_empty() {
+                continue;
+            }
+
+            let body = Body {
+                kind: BodyKind::Gossip { messages: values },
+                msg_id: self.inner.next_id(),
+                reply_to: None,
+            };
+            ret.push(Message {
+                src: src.to_string(),
+                dst: node_id,
+                body,
+            });
+        }
+        ret
+    }
+
+    /**
+     * periodic flush, every `retry_rounds`-th one also resends unacknowledged values
+     */
+    pub fn tick(&self) -> Vec<Message> {
+        let retry = {
+            let mut delivery = self.delivery.lock().unwrap();
+            delivery.round += 1;
+            delivery.round.is_multiple_of(self.config.retry_rounds)
+        };
+        self.flush(retry)
+    }
+
+    /**
+     * keep the values gossiped by a neighbor and acknowledge them
+     */
+    pub fn merge(&self, msg: &Message, values: &[usize]) -> Message {
+        let added = {
+            let mut messages = self.messages.lock().unwrap();
+            values
+                .iter()
+                .filter(|value| messages.insert(**value))
+                .count()
+        };
+        self.ack(&msg.src, values);
+        if added > 0 {
+            self.added(added);
+        }
+
+        let body = Body {
+            kind: BodyKind::GossipOk {
+                messages: values.to_vec(),
+            },
+            msg_id: self.inner.next_id(),
+            reply_to: Some(msg.body.msg_id),
+        };
+
+        Message {
+            src: self.inner.node_id().to_string(),
+            dst: msg.src.clone(),
+            body,
+        }
+    }
+
+    /**
+     * node has values, stop sending them to it
+     */
+    fn ack(&self, node_id: &str, values: &[usize]) {
+        let mut delivery = self.delivery.lock().unwrap();
+        let neighbor = delivery.neighbors.entry(node_id.to_string()).or_default();
+        for value in values {
+            neighbor.in_flight.remove(value);
+            neighbor.known.insert(*value);
+        }
     }
 
     /**
      * This message requests that a node return all values that it has seen.
+     * with `since`, only the values added after that version are returned,
+     * along with the current version. a plain read replies with `messages`
+     * only, as maelstrom expects
      */
-    pub fn read(&mut self, msg: &Message) -> Message {
+    pub fn read(&self, msg: &Message, since: Option<usize>) -> Message {
+        let messages = self.messages.lock().unwrap();
         let body = Body {
-            kind: BodyKind::ReadOk,
-            msg_id: self.inner.next_msg_id(),
+            kind: BodyKind::ReadOk {
+                messages: Some(messages.since(since.unwrap_or_default()).to_vec()),
+                version: since.map(|_| messages.version()),
+                value: None,
+            },
+            msg_id: self.inner.next_id(),
             reply_to: Some(msg.body.msg_id),
-            payload: Payload::init("messages", json!(self.messages)),
         };
 
         Message {
@@ -102,25 +338,16 @@ impl BroadcastServer {
     *      "n2": ["n1"],
     *      "n3": ["n1"]
        }
+    * the neighbors kept are the ones `config.topology` builds from it
     */
-    pub fn topology(&mut self, msg: &Message) -> Message {
-        self.topology = HashMap::new();
-        let topology_value = msg.body.payload.get_raw("topology");
-        for (k, v) in topology_value.as_object().unwrap().iter() {
-            let dsts: Vec<String> = v
-                .as_array()
-                .unwrap()
-                .iter()
-                .map(|item| item.as_str().unwrap().to_string())
-                .collect();
-            self.topology.insert(k.to_string(), dsts);
-        }
+    pub fn topology(&self, msg: &Message, topology: &HashMap<String, Vec<String>>) -> Message {
+        let topology = self.config.topology.build(self.inner.node_ids(), topology);
+        *self.topology.lock().unwrap() = topology;
 
         let body = Body {
             kind: BodyKind::TopologyOk,
-            msg_id: self.inner.next_msg_id(),
+            msg_id: self.inner.next_id(),
             reply_to: Some(msg.body.msg_id),
-            payload: Payload::default(),
         };
 
         Message {
@@ -133,57 +360,381 @@ impl BroadcastServer {
 
 #[async_trait]
 impl Serve for BroadcastServer {
-    async fn reply(&mut self, msg: &Message) -> Option<Message> {
+    async fn reply(&self, msg: &Message) -> error::Result<Option<Message>> {
         match &msg.body.kind {
-            BodyKind::Init => self.inner.init(msg),
-            BodyKind::Broadcast => Some(self.broadcast(msg)),
-            BodyKind::Read => Some(self.read(msg)),
-            BodyKind::Topology => Some(self.topology(msg)),
-            // receive broadcast_ok msg from other nodes which reply to self's broadcast msg
-            BodyKind::BroadcastOk => None,
-            _ => panic!("receive ${:?}", msg),
+            BodyKind::Init { .. } => Ok(self.inner.init(msg)),
+            BodyKind::Broadcast { message } => Ok(Some(self.broadcast(msg, *message))),
+            BodyKind::Read { since, .. } => Ok(Some(self.read(msg, *since))),
+            BodyKind::Topology { topology } => Ok(Some(self.topology(msg, topology))),
+            BodyKind::Gossip { messages } => Ok(Some(self.merge(msg, messages))),
+            BodyKind::GossipOk { messages } => {
+                self.ack(&msg.src, messages);
+                Ok(None)
+            }
+            _ => Err(Error::NotSupported(format!("cannot handle msg: {:?}", msg))),
         }
     }
 
-    async fn send(&mut self) -> Option<Vec<Message>> {
-        self.broadcast_back()
+    fn schedule(self: Arc<Self>) {
+        let server = self.clone();
+        let flush_interval = self.config.flush_interval;
+        self.inner.timers().every(flush_interval, move || {
+            let server = server.clone();
+            async move {
+                for msg in server.tick() {
+                    server.inner.send(msg);
+                }
+            }
+        });
     }
 }
 
 impl HasInner for BroadcastServer {
-    fn as_inner(&mut self) -> &mut ServerInner {
-        &mut self.inner
+    fn as_inner(&self) -> &ServerInner {
+        &self.inner
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let mut server = BroadcastServer::default();
+    let server = BroadcastServer::new(BroadcastConfig::from_env());
     server.serve().await
 }
 
 #[cfg(test)]
 mod tests {
-    use dist_sys_rs::message::{Body, BodyKind, Message, Payload};
+    use std::{collections::HashMap, time::Duration};
+
+    use dist_sys_rs::{
+        checker::broadcast,
+        harness::{Cluster, SimConfig, Simulation},
+        message::{Body, BodyKind, Message},
+        server::Serve,
+        topology,
+    };
     use serde_json::json;
 
-    use crate::BroadcastServer;
+    use crate::{BroadcastConfig, BroadcastServer, MessageStore};
+
+    fn new_server(config: BroadcastConfig) -> BroadcastServer {
+        let server = BroadcastServer::new(config);
+        server.inner.set_node_id("n1");
+        server
+            .inner
+            .set_node_ids(&["n1".to_string(), "n2".to_string(), "n3".to_string()]);
+        server
+    }
+
+    fn gossip_dsts(server: &BroadcastServer, retry: bool) -> Vec<(String, BodyKind)> {
+        let mut dsts: Vec<(String, BodyKind)> = server
+            .flush(retry)
+            .into_iter()
+            .map(|msg| (msg.dst, msg.body.kind))
+            .collect();
+        dsts.sort_by(|a, b| a.0.cmp(&b.0));
+        dsts
+    }
+
+    fn from(src: &str, kind: BodyKind) -> Message {
+        Message {
+            src: src.to_string(),
+            dst: "n1".to_string(),
+            body: Body {
+                kind,
+                msg_id: 1,
+                reply_to: None,
+            },
+        }
+    }
 
     #[test]
     fn test_broadcast() {
-        let mut server = BroadcastServer::default();
+        let server = BroadcastServer::default();
         let msg = Message {
             src: "n1".to_string(),
             dst: "n2".to_string(),
             body: Body {
-                kind: BodyKind::Broadcast,
+                kind: BodyKind::Broadcast { message: 10 },
                 reply_to: None,
-                payload: Payload::init("message", json!(10)),
                 msg_id: 100,
             },
         };
-        let reply_msg = server.broadcast(&msg);
+        let reply_msg = server.broadcast(&msg, 10);
         assert_eq!(BodyKind::BroadcastOk, reply_msg.body.kind);
         assert_eq!(100, reply_msg.body.reply_to.unwrap());
+
+        // a value broadcast twice is kept once
+        server.broadcast(&msg, 10);
+        assert_eq!(1, server.messages.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_message_store() {
+        let mut store = MessageStore::default();
+        assert!(store.insert(7));
+        assert!(store.insert(3));
+        assert!(!store.insert(7));
+        assert_eq!(2, store.version());
+        assert_eq!([7, 3], store.since(0));
+        assert_eq!([3], store.since(1));
+        assert!(store.since(5).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delta_read() {
+        let server = new_server(BroadcastConfig::default());
+        for value in [5, 1, 5, 9] {
+            server.broadcast(&from("c1", BodyKind::Broadcast { message: value }), value);
+        }
+
+        let server = &server;
+        let read = |since| async move {
+            let reply_msg = server
+                .handle(&from("c1", BodyKind::Read { key: None, since }))
+                .await
+                .unwrap();
+            reply_msg.body.kind
+        };
+        assert_eq!(
+            BodyKind::ReadOk {
+                messages: Some(vec![5, 1, 9]),
+                version: Some(3),
+                value: None,
+            },
+            read(Some(0)).await
+        );
+        assert_eq!(
+            BodyKind::ReadOk {
+                messages: Some(vec![9]),
+                version: Some(3),
+                value: None,
+            },
+            read(Some(2)).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plain_read_ok() {
+        let server = new_server(BroadcastConfig::default());
+        server.broadcast(&from("c1", BodyKind::Broadcast { message: 5 }), 5);
+
+        let read = BodyKind::Read {
+            key: None,
+            since: None,
+        };
+        let reply_msg = server.handle(&from("c1", read)).await.unwrap();
+        let mut body = serde_json::to_value(&reply_msg.body).unwrap();
+        let fields = body.as_object_mut().unwrap();
+        assert!(fields.remove("msg_id").is_some());
+        assert!(fields.remove("in_reply_to").is_some());
+        assert_eq!(json!({"type": "read_ok", "messages": [5]}), body);
+    }
+
+    #[tokio::test]
+    async fn test_gossip_until_acked() {
+        let server = new_server(BroadcastConfig::default());
+        server.messages.lock().unwrap().insert(10);
+        let gossip = BodyKind::Gossip { messages: vec![10] };
+
+        // without a topology every peer is a neighbor
+        let expected = vec![
+            ("n2".to_string(), gossip.clone()),
+            ("n3".to_string(), gossip.clone()),
+        ];
+        assert_eq!(expected, gossip_dsts(&server, false));
+        // in flight values wait for a retry
+        assert!(gossip_dsts(&server, false).is_empty());
+        assert_eq!(expected, gossip_dsts(&server, true));
+
+        let ack = from("n2", BodyKind::GossipOk { messages: vec![10] });
+        assert_eq!(None, server.handle(&ack).await);
+        assert_eq!(vec![("n3".to_string(), gossip)], gossip_dsts(&server, true));
+
+        // values gossiped from n3 are kept and known to n3 already
+        let reply_msg = server
+            .handle(&from(
+                "n3",
+                BodyKind::Gossip {
+                    messages: vec![10, 11],
+                },
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            BodyKind::GossipOk {
+                messages: vec![10, 11]
+            },
+            reply_msg.body.kind
+        );
+        assert_eq!(
+            vec![("n2".to_string(), BodyKind::Gossip { messages: vec![11] })],
+            gossip_dsts(&server, false)
+        );
+    }
+
+    #[test]
+    fn test_flush_full_batch() {
+        let server = new_server(BroadcastConfig {
+            batch_size: 2,
+            ..Default::default()
+        });
+        let mut outbox = server.inner.take_outbox().unwrap();
+        let msg = from("c1", BodyKind::Broadcast { message: 1 });
+
+        server.broadcast(&msg, 1);
+        assert!(outbox.try_recv().is_err());
+
+        // a value seen before doesn't count
+        server.broadcast(&msg, 1);
+        assert!(outbox.try_recv().is_err());
+
+        server.broadcast(&msg, 2);
+        for dst in ["n2", "n3"] {
+            let gossip_msg = outbox.try_recv().unwrap();
+            assert_eq!(dst, gossip_msg.dst);
+            assert_eq!(
+                BodyKind::Gossip {
+                    messages: vec![1, 2]
+                },
+                gossip_msg.body.kind
+            );
+        }
+        assert!(outbox.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_converge_in_cluster() {
+        let mut cluster: Cluster<BroadcastServer> = Cluster::new(5).await;
+        let client = cluster.client();
+        for i in 0..10 {
+            let node_id = format!("n{}", i % 5);
+            client
+                .request(&node_id, BodyKind::Broadcast { message: i })
+                .await
+                .unwrap();
+        }
+
+        let converged = cluster
+            .wait_until(Duration::from_secs(3), |cluster| {
+                cluster
+                    .nodes()
+                    .all(|(_, node)| node.messages.lock().unwrap().len() == 10)
+            })
+            .await;
+        assert!(converged);
+    }
+
+    #[tokio::test]
+    async fn test_converge_after_partition() {
+        let config = SimConfig::new(13).latency(1, 50).drop_rate(0.1);
+        let mut sim: Simulation<BroadcastServer> = Simulation::new(5, config).await;
+
+        sim.partition(&[&["n0", "n1"], &["n2", "n3", "n4"]]);
+        for i in 0..10 {
+            let node_id = format!("n{}", i % 5);
+            let reply_msg = sim.call(&node_id, BodyKind::Broadcast { message: i }).await;
+            assert_eq!(BodyKind::BroadcastOk, reply_msg.unwrap().body.kind);
+        }
+        sim.run_for(1_000).await;
+        assert!(!sim.node("n0").messages.lock().unwrap().contains(&2));
+
+        sim.heal();
+        sim.run_for(2_000).await;
+        for node_id in sim.node_ids() {
+            sim.call(
+                &node_id,
+                BodyKind::Read {
+                    key: None,
+                    since: None,
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let violations = broadcast::check(sim.history());
+        assert!(violations.is_empty(), "{:?}", violations);
+    }
+
+    async fn msgs_per_op(config: BroadcastConfig) -> f64 {
+        let nodes = (0..5)
+            .map(|_| BroadcastServer::new(config.clone()))
+            .collect();
+        let mut sim = Simulation::with_nodes(nodes, SimConfig::new(5)).await;
+        for i in 0..50 {
+            sim.request(
+                "c1",
+                &format!("n{}", i % 5),
+                BodyKind::Broadcast { message: i },
+            );
+            sim.run_for(5).await;
+        }
+        sim.run_for(1_000).await;
+        for (_, node) in sim.nodes() {
+            assert_eq!(50, node.messages.lock().unwrap().len());
+        }
+
+        let stats = sim.stats();
+        assert_eq!(50 + 5, stats.client_requests);
+        stats.msgs_per_op()
+    }
+
+    #[tokio::test]
+    async fn test_batching_cuts_messages() {
+        let unbatched = msgs_per_op(BroadcastConfig {
+            batch_size: 1,
+            ..Default::default()
+        })
+        .await;
+        let batched = msgs_per_op(BroadcastConfig::default()).await;
+        assert!(batched * 4.0 < unbatched, "{} vs {}", batched, unbatched);
+    }
+
+    #[tokio::test]
+    async fn test_topology_strategy() {
+        let config = BroadcastConfig {
+            topology: topology::parse("star").unwrap(),
+            ..Default::default()
+        };
+        let nodes = (0..4)
+            .map(|_| BroadcastServer::new(config.clone()))
+            .collect();
+        let mut sim = Simulation::with_nodes(nodes, SimConfig::new(2)).await;
+
+        // a line n0 - n1 - n2 - n3 from maelstrom is replaced by a star around n0
+        let ids = sim.node_ids();
+        let given: HashMap<String, Vec<String>> = (0..4)
+            .map(|i: usize| {
+                let others = [i.checked_sub(1), Some(i + 1).filter(|j| *j < 4)];
+                let others = others.into_iter().flatten().map(|j| ids[j].clone());
+                (ids[i].clone(), others.collect())
+            })
+            .collect();
+        for node_id in ids.iter() {
+            let kind = BodyKind::Topology {
+                topology: given.clone(),
+            };
+            assert!(sim.call(node_id, kind).await.is_some());
+        }
+        assert_eq!(
+            Some(&vec!["n0".to_string()]),
+            sim.node("n2").topology.lock().unwrap().get("n2")
+        );
+
+        sim.call("n3", BodyKind::Broadcast { message: 1 }).await;
+        sim.run_for(500).await;
+        for node_id in ids.iter() {
+            sim.call(
+                node_id,
+                BodyKind::Read {
+                    key: None,
+                    since: None,
+                },
+            )
+            .await;
+        }
+        let violations = broadcast::check(sim.history());
+        assert!(violations.is_empty(), "{:?}", violations);
+        assert!(!sim.trace().iter().any(|line| line.contains("n3 -> n2")));
     }
 }
@@ -1,11 +1,10 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use core::panic;
 use dist_sys_rs::{
-    message::{Body, BodyKind, Message, Payload},
+    error::{self, Error},
+    message::{Body, BodyKind, Message},
     server::{HasInner, Serve, ServerInner},
 };
-use serde_json::json;
 
 #[derive(Debug, Default)]
 pub struct EchoServer {
@@ -13,12 +12,13 @@ pub struct EchoServer {
 }
 
 impl EchoServer {
-    fn echo(&mut self, msg: &Message) -> Message {
+    fn echo(&self, msg: &Message, echo: &str) -> Message {
         let body = Body {
-            kind: BodyKind::EchoOk,
-            msg_id: self.inner.next_msg_id(),
+            kind: BodyKind::EchoOk {
+                echo: echo.to_string(),
+            },
+            msg_id: self.inner.next_id(),
             reply_to: Some(msg.body.msg_id),
-            payload: Payload::init("echo", json!(msg.body.payload.get_str("echo"))),
         };
 
         Message {
@@ -31,50 +31,77 @@ impl EchoServer {
 
 #[async_trait]
 impl Serve for EchoServer {
-    async fn reply(&mut self, msg: &Message) -> Option<Message> {
-        match msg.body.kind {
-            BodyKind::Init => self.inner.init(msg),
-            BodyKind::Echo => Some(self.echo(msg)),
-            _ => panic!("{}", format!("cannot handle msg: {:?}", msg)),
+    async fn reply(&self, msg: &Message) -> error::Result<Option<Message>> {
+        match &msg.body.kind {
+            BodyKind::Init { .. } => Ok(self.inner.init(msg)),
+            BodyKind::Echo { echo } => Ok(Some(self.echo(msg, echo))),
+            _ => Err(Error::NotSupported(format!("cannot handle msg: {:?}", msg))),
         }
     }
 }
 
 impl HasInner for EchoServer {
-    fn as_inner(&mut self) -> &mut ServerInner {
-        &mut self.inner
+    fn as_inner(&self) -> &ServerInner {
+        &self.inner
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let mut server = EchoServer::default();
+    let server = EchoServer::default();
     server.serve().await
 }
 
 #[cfg(test)]
 mod tests {
-    use dist_sys_rs::message::{Body, BodyKind, Message, Payload};
-    use serde_json::json;
+    use dist_sys_rs::{
+        error::ErrorCode,
+        message::{Body, BodyKind, Message, MessageBuilder},
+        server::Serve,
+    };
 
     use crate::EchoServer;
 
     #[test]
     fn test_echo() {
-        let mut server = EchoServer::default();
+        let server = EchoServer::default();
         let msg = Message {
             src: "n1".to_string(),
             dst: "n2".to_string(),
             body: Body {
-                kind: BodyKind::Echo,
+                kind: BodyKind::Echo {
+                    echo: "hhh".to_string(),
+                },
                 reply_to: None,
-                payload: Payload::init("echo", json!("hhh")),
                 msg_id: 100,
             },
         };
-        let reply_msg = server.echo(&msg);
-        assert_eq!(BodyKind::EchoOk, reply_msg.body.kind);
+        let reply_msg = server.echo(&msg, "hhh");
+        assert_eq!(
+            BodyKind::EchoOk {
+                echo: "hhh".to_string()
+            },
+            reply_msg.body.kind
+        );
         assert_eq!(100, reply_msg.body.reply_to.unwrap());
-        assert_eq!("hhh", reply_msg.body.payload.get_str("echo"));
+    }
+
+    #[tokio::test]
+    async fn test_not_supported() {
+        let server = EchoServer::default();
+        let msg = MessageBuilder::new()
+            .bodykind(BodyKind::Generate)
+            .msg_id(7)
+            .build();
+
+        let reply_msg = server.handle(&msg).await.unwrap();
+        assert_eq!(Some(7), reply_msg.body.reply_to);
+        assert!(matches!(
+            reply_msg.body.kind,
+            BodyKind::Error {
+                code: ErrorCode::NotSupported,
+                ..
+            }
+        ));
     }
 }
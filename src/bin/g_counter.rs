@@ -0,0 +1,16 @@
+use anyhow::Result;
+use std::env;
+
+use dist_sys_rs::{counter_server::CounterServer, crdt::GCounter, kv::KvClient, server::Serve};
+
+/// kv service to keep every node's slot in as well, e.g. `seq-kv`, unset by default
+const KV_ENV: &str = "DIST_SYS_G_COUNTER_KV";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let server = match env::var(KV_ENV) {
+        Ok(service) => CounterServer::<GCounter>::with_kv(KvClient::new(&service)),
+        Err(_) => CounterServer::default(),
+    };
+    server.serve().await
+}
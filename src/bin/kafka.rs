@@ -1,8 +1,11 @@
 use anyhow::Result;
-use dist_sys_rs::{kafka::KafkaServer, server::Serve};
+use dist_sys_rs::{
+    kafka::{KafkaServer, StorageConfig},
+    server::Serve,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let mut server = KafkaServer::default();
+    let server = KafkaServer::new(StorageConfig::from_env());
     server.serve().await
 }
@@ -0,0 +1,9 @@
+use anyhow::Result;
+
+use dist_sys_rs::{counter_server::CounterServer, crdt::PNCounter, server::Serve};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let server = CounterServer::<PNCounter>::default();
+    server.serve().await
+}
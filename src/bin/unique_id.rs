@@ -1,12 +1,14 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use core::panic;
 use dist_sys_rs::{
-    message::{Body, BodyKind, Message, Payload},
+    error::{self, Error},
+    message::{Body, BodyKind, Message},
     server::{HasInner, Serve, ServerInner},
 };
-use serde_json::json;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 fn current_time_millis() -> u64 {
     SystemTime::now()
@@ -16,35 +18,42 @@ fn current_time_millis() -> u64 {
 }
 
 #[derive(Debug, Default)]
-pub struct UniqueIdServer {
-    inner: ServerInner,
+struct IdClock {
     /// last-generated time
-    pub last_ts: u64,
+    last_ts: u64,
     /// a counter for IDs generated at that timestamp
-    pub count: usize,
+    count: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct UniqueIdServer {
+    inner: ServerInner,
+    clock: Mutex<IdClock>,
 }
 
 impl UniqueIdServer {
-    fn compose_id(&self) -> String {
-        format!("{}{}{}", self.inner.node_id(), self.last_ts, self.count)
+    fn compose_id(&self, clock: &IdClock) -> String {
+        format!("{}{}{}", self.inner.node_id(), clock.last_ts, clock.count)
     }
 
-    fn generate_id(&mut self) -> String {
-        let ts = std::cmp::max(current_time_millis(), self.last_ts);
-        if ts == self.last_ts {
-            self.count += 1;
+    fn generate_id(&self) -> String {
+        let mut clock = self.clock.lock().unwrap();
+        let ts = std::cmp::max(current_time_millis(), clock.last_ts);
+        if ts == clock.last_ts {
+            clock.count += 1;
         }
-        self.last_ts = ts;
+        clock.last_ts = ts;
 
-        self.compose_id()
+        self.compose_id(&clock)
     }
 
-    pub fn generate(&mut self, msg: &Message) -> Message {
+    pub fn generate(&self, msg: &Message) -> Message {
         let body = Body {
-            kind: BodyKind::GenerateOk,
-            msg_id: self.inner.next_msg_id(),
+            kind: BodyKind::GenerateOk {
+                id: self.generate_id(),
+            },
+            msg_id: self.inner.next_id(),
             reply_to: Some(msg.body.msg_id),
-            payload: Payload::init("id", json!(self.generate_id())),
         };
 
         Message {
@@ -57,23 +66,55 @@ impl UniqueIdServer {
 
 #[async_trait]
 impl Serve for UniqueIdServer {
-    async fn reply(&mut self, msg: &Message) -> Option<Message> {
+    async fn reply(&self, msg: &Message) -> error::Result<Option<Message>> {
         match &msg.body.kind {
-            BodyKind::Init => self.inner.init(msg),
-            BodyKind::Generate => Some(self.generate(msg)),
-            _ => panic!("receive ${:?}", msg),
+            BodyKind::Init { .. } => Ok(self.inner.init(msg)),
+            BodyKind::Generate => Ok(Some(self.generate(msg))),
+            _ => Err(Error::NotSupported(format!("cannot handle msg: {:?}", msg))),
         }
     }
 }
 
 impl HasInner for UniqueIdServer {
-    fn as_inner(&mut self) -> &mut ServerInner {
-        &mut self.inner
+    fn as_inner(&self) -> &ServerInner {
+        &self.inner
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let mut server = UniqueIdServer::default();
+    let server = UniqueIdServer::default();
     server.serve().await
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use dist_sys_rs::{checker::unique_id, harness::Cluster, message::BodyKind};
+
+    use crate::UniqueIdServer;
+
+    #[tokio::test]
+    async fn test_unique_across_nodes() {
+        let mut cluster: Cluster<UniqueIdServer> = Cluster::new(3).await;
+        let client = cluster.client();
+
+        let mut calls = Vec::new();
+        for i in 0..300 {
+            let node_id = format!("n{}", i % 3);
+            calls.push(tokio::spawn(client.request(&node_id, BodyKind::Generate)));
+        }
+
+        let mut ids = HashSet::new();
+        for call in calls {
+            let reply_msg = call.await.unwrap().unwrap();
+            let BodyKind::GenerateOk { id } = reply_msg.body.kind else {
+                panic!("expect generate_ok, got {:?}", reply_msg.body.kind);
+            };
+            assert!(ids.insert(id));
+        }
+        assert_eq!(300, ids.len());
+        assert!(unique_id::check(&cluster.history()).is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::message::BodyKind;
+
+use super::{History, Op, Violation};
+
+/**
+ * every acknowledged broadcast shows up in the last read of every node,
+ * unless that read started before the broadcast was acknowledged,
+ * and no read returns a value nobody broadcast
+ */
+pub fn check(history: &History) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    let mut broadcast = HashSet::new();
+    let mut acked: Vec<(usize, &Op, usize)> = Vec::new();
+    // last completed read of every node broadcast to or read from
+    let mut last_reads: BTreeMap<&str, Option<(usize, &Op)>> = BTreeMap::new();
+
+    for (i, op) in history.ops().iter().enumerate() {
+        match &op.request {
+            BodyKind::Broadcast { message } => {
+                broadcast.insert(*message);
+                if op.is_ok() {
+                    acked.push((i, op, *message));
+                }
+                last_reads.entry(&op.node).or_default();
+            }
+            // a delta read only returns part of the values
+            BodyKind::Read { since: None, .. } => {
+                let last_read = last_reads.entry(&op.node).or_default();
+                if !op.is_ok() {
+                    continue;
+                }
+                let newer = match last_read {
+                    Some((_, read)) => read.completed < op.completed,
+                    None => true,
+                };
+                if newer {
+                    *last_read = Some((i, op));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    for (node, last_read) in last_reads {
+        let Some((i, read)) = last_read else {
+            violations.push(Violation::new(None, format!("{} was never read", node)));
+            continue;
+        };
+        let Some(BodyKind::ReadOk {
+            messages: Some(messages),
+            ..
+        }) = &read.reply
+        else {
+            violations.push(Violation::new(
+                Some(i),
+                format!("expect read_ok, got {:?}", read.reply),
+            ));
+            continue;
+        };
+
+        for value in messages.iter().filter(|value| !broadcast.contains(value)) {
+            violations.push(Violation::new(
+                Some(i),
+                format!("{} read {} which was never broadcast", node, value),
+            ));
+        }
+
+        let seen: HashSet<&usize> = messages.iter().collect();
+        for (j, op, value) in acked.iter() {
+            if op.precedes(read) && !seen.contains(value) {
+                violations.push(Violation::new(
+                    Some(*j),
+                    format!(
+                        "{} acknowledged but missing from last read of {}",
+                        value, node
+                    ),
+                ));
+            }
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{checker::History, message::BodyKind};
+
+    use super::check;
+
+    fn broadcast(history: &mut History, node: &str, message: usize) {
+        let op = history.invoke("c1", node, BodyKind::Broadcast { message });
+        history.complete(op, BodyKind::BroadcastOk);
+    }
+
+    fn read(history: &mut History, node: &str, messages: Vec<usize>) {
+        let op = history.invoke(
+            "c1",
+            node,
+            BodyKind::Read {
+                key: None,
+                since: None,
+            },
+        );
+        history.complete(
+            op,
+            BodyKind::ReadOk {
+                messages: Some(messages),
+                version: None,
+                value: None,
+            },
+        );
+    }
+
+    #[test]
+    fn test_valid() {
+        let mut history = History::default();
+        broadcast(&mut history, "n0", 1);
+        broadcast(&mut history, "n1", 2);
+        // broadcast but never acknowledged, it may show up or not
+        history.invoke("c2", "n1", BodyKind::Broadcast { message: 3 });
+        read(&mut history, "n0", vec![2]);
+        read(&mut history, "n0", vec![1, 2, 3]);
+        read(&mut history, "n1", vec![2, 1]);
+
+        assert_eq!(Vec::<super::Violation>::new(), check(&history));
+    }
+
+    #[test]
+    fn test_lost_and_unexpected() {
+        let mut history = History::default();
+        broadcast(&mut history, "n0", 1);
+        broadcast(&mut history, "n0", 2);
+        read(&mut history, "n0", vec![1, 2]);
+        read(&mut history, "n1", vec![1, 9]);
+        broadcast(&mut history, "n2", 4);
+
+        let violations = check(&history);
+        let texts: Vec<&str> = violations.iter().map(|v| v.text.as_str()).collect();
+        assert_eq!(
+            vec![
+                "n1 read 9 which was never broadcast",
+                "2 acknowledged but missing from last read of n1",
+                "n2 was never read",
+            ],
+            texts
+        );
+        assert_eq!(Some(1), violations[1].op);
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::message::BodyKind;
+
+use super::{History, Op, Violation};
+
+/// an acknowledged send
+struct Sent<'a> {
+    op: usize,
+    key: &'a str,
+    msg: usize,
+    offset: usize,
+}
+
+/**
+ * for every key:
+ * acknowledged sends get distinct offsets, growing in real time order,
+ * polls return them in offset order without skipping or altering any of them,
+ * polls never skip a committed msg, one seen by an earlier poll at or before
+ * the committed offset, and committed offsets never go backwards
+ */
+pub fn check(history: &History) -> Vec<Violation> {
+    let ops = history.ops();
+    let sends = acked_sends(ops);
+
+    let mut violations = Vec::new();
+    check_offsets(ops, &sends, &mut violations);
+    check_polls(ops, &sends, &mut violations);
+    check_commits(ops, &mut violations);
+    violations
+}
+
+fn acked_sends(ops: &[Op]) -> Vec<Sent<'_>> {
+    ops.iter()
+        .enumerate()
+        .filter_map(|(i, op)| match (&op.request, &op.reply) {
+            (BodyKind::Send { key, msg }, Some(BodyKind::SendOk { offset })) => Some(Sent {
+                op: i,
+                key,
+                msg: *msg,
+                offset: *offset,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn check_offsets(ops: &[Op], sends: &[Sent], violations: &mut Vec<Violation>) {
+    let mut owners: HashMap<(&str, usize), &Sent> = HashMap::new();
+    for sent in sends {
+        if let Some(first) = owners.insert((sent.key, sent.offset), sent) {
+            violations.push(Violation::new(
+                Some(sent.op),
+                format!(
+                    "offset {} of {} given to both msg {} and msg {}",
+                    sent.offset, sent.key, first.msg, sent.msg
+                ),
+            ));
+        }
+    }
+
+    for a in sends {
+        for b in sends.iter().filter(|b| b.key == a.key) {
+            if ops[a.op].precedes(&ops[b.op]) && a.offset >= b.offset {
+                violations.push(Violation::new(
+                    Some(b.op),
+                    format!(
+                        "{} got offset {} after an earlier send got {}",
+                        b.key, b.offset, a.offset
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+fn check_polls(ops: &[Op], sends: &[Sent], violations: &mut Vec<Violation>) {
+    let by_offset: HashMap<(&str, usize), &Sent> = sends
+        .iter()
+        .map(|sent| ((sent.key, sent.offset), sent))
+        .collect();
+
+    for (i, op) in ops.iter().enumerate() {
+        let (BodyKind::Poll { offsets }, Some(BodyKind::PollOk { msgs })) =
+            (&op.request, &op.reply)
+        else {
+            continue;
+        };
+
+        let msgs: BTreeMap<&String, &Vec<[usize; 2]>> = msgs.iter().collect();
+        for (key, entries) in msgs {
+            let start = offsets.get(key).copied().unwrap_or_default();
+            let mut last: Option<usize> = None;
+            for &[offset, msg] in entries.iter() {
+                if offset < start {
+                    violations.push(Violation::new(
+                        Some(i),
+                        format!("poll of {} from {} returned offset {}", key, start, offset),
+                    ));
+                }
+                if matches!(last, Some(last) if last >= offset) {
+                    violations.push(Violation::new(
+                        Some(i),
+                        format!("poll of {} returned offset {} out of order", key, offset),
+                    ));
+                }
+                last = Some(offset);
+
+                if let Some(sent) = by_offset.get(&(key.as_str(), offset)) {
+                    if sent.msg != msg {
+                        violations.push(Violation::new(
+                            Some(i),
+                            format!(
+                                "poll of {} returned msg {} at offset {}, where msg {} was acknowledged",
+                                key, msg, offset, sent.msg
+                            ),
+                        ));
+                    }
+                }
+            }
+
+            let Some(last) = last else {
+                continue;
+            };
+            let returned = |offset: usize| entries.iter().any(|[o, _]| *o == offset);
+            let mut skipped = HashSet::new();
+            for sent in sends.iter().filter(|sent| sent.key == key.as_str()) {
+                let in_range = start <= sent.offset && sent.offset <= last;
+                if in_range && !returned(sent.offset) && ops[sent.op].precedes(op) {
+                    skipped.insert(sent.offset);
+                    violations.push(Violation::new(
+                        Some(i),
+                        format!(
+                            "poll of {} skipped acknowledged msg {} at offset {}",
+                            key, sent.msg, sent.offset
+                        ),
+                    ));
+                }
+            }
+
+            let Some(committed) = committed_offset(ops, op, key) else {
+                continue;
+            };
+            for (offset, msg) in polled_before(ops, op, key) {
+                let in_range = start <= offset && offset <= last.min(committed);
+                if in_range && !returned(offset) && skipped.insert(offset) {
+                    violations.push(Violation::new(
+                        Some(i),
+                        format!(
+                            "poll of {} skipped committed msg {} at offset {}",
+                            key, msg, offset
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/**
+ * the highest offset of key committed before op was invoked
+ */
+fn committed_offset(ops: &[Op], op: &Op, key: &str) -> Option<usize> {
+    ops.iter()
+        .filter(|commit| commit.is_ok() && commit.precedes(op))
+        .filter_map(|commit| match &commit.request {
+            BodyKind::CommitOffsets { offsets } => offsets.get(key).copied(),
+            _ => None,
+        })
+        .max()
+}
+
+/**
+ * (offset, msg) of key returned by polls completed before op was invoked
+ */
+fn polled_before(ops: &[Op], op: &Op, key: &str) -> BTreeMap<usize, usize> {
+    ops.iter()
+        .filter(|poll| poll.precedes(op))
+        .filter_map(|poll| match &poll.reply {
+            Some(BodyKind::PollOk { msgs }) => msgs.get(key),
+            _ => None,
+        })
+        .flatten()
+        .map(|&[offset, msg]| (offset, msg))
+        .collect()
+}
+
+fn check_commits(ops: &[Op], violations: &mut Vec<Violation>) {
+    let commits: Vec<&Op> = ops
+        .iter()
+        .filter(|op| matches!(op.request, BodyKind::CommitOffsets { .. }) && op.is_ok())
+        .collect();
+
+    for (i, op) in ops.iter().enumerate() {
+        let (
+            BodyKind::ListCommittedOffsets { keys },
+            Some(BodyKind::ListCommittedOffsetsOk { offsets }),
+        ) = (&op.request, &op.reply)
+        else {
+            continue;
+        };
+
+        for commit in commits.iter().filter(|commit| commit.precedes(op)) {
+            let BodyKind::CommitOffsets { offsets: committed } = &commit.request else {
+                continue;
+            };
+            for key in keys.iter() {
+                let Some(committed) = committed.get(key) else {
+                    continue;
+                };
+                match offsets.get(key) {
+                    Some(listed) if listed >= committed => (),
+                    listed => violations.push(Violation::new(
+                        Some(i),
+                        format!(
+                            "{} was committed at {} but listed at {:?}",
+                            key, committed, listed
+                        ),
+                    )),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{checker::History, message::BodyKind};
+
+    use super::check;
+
+    fn send(history: &mut History, key: &str, msg: usize, offset: usize) -> usize {
+        let op = history.invoke(
+            "c1",
+            "n0",
+            BodyKind::Send {
+                key: key.to_string(),
+                msg,
+            },
+        );
+        history.complete(op, BodyKind::SendOk { offset });
+        op
+    }
+
+    fn poll(history: &mut History, key: &str, from: usize, entries: Vec<[usize; 2]>) {
+        let op = history.invoke(
+            "c1",
+            "n0",
+            BodyKind::Poll {
+                offsets: HashMap::from([(key.to_string(), from)]),
+            },
+        );
+        history.complete(
+            op,
+            BodyKind::PollOk {
+                msgs: HashMap::from([(key.to_string(), entries)]),
+            },
+        );
+    }
+
+    fn texts(history: &History) -> Vec<String> {
+        check(history).into_iter().map(|v| v.text).collect()
+    }
+
+    #[test]
+    fn test_valid() {
+        let mut history = History::default();
+        send(&mut history, "k1", 10, 0);
+        send(&mut history, "k1", 11, 1);
+        send(&mut history, "k2", 20, 0);
+        poll(&mut history, "k1", 0, vec![[0, 10], [1, 11]]);
+        poll(&mut history, "k1", 1, vec![[1, 11]]);
+
+        let commit = history.invoke(
+            "c1",
+            "n0",
+            BodyKind::CommitOffsets {
+                offsets: HashMap::from([("k1".to_string(), 1)]),
+            },
+        );
+        history.complete(commit, BodyKind::CommitOffsetsOk);
+        let list = history.invoke(
+            "c1",
+            "n0",
+            BodyKind::ListCommittedOffsets {
+                keys: vec!["k1".to_string()],
+            },
+        );
+        history.complete(
+            list,
+            BodyKind::ListCommittedOffsetsOk {
+                offsets: HashMap::from([("k1".to_string(), 1)]),
+            },
+        );
+
+        assert!(texts(&history).is_empty(), "{:?}", texts(&history));
+    }
+
+    #[test]
+    fn test_offsets() {
+        let mut history = History::default();
+        send(&mut history, "k1", 10, 1);
+        send(&mut history, "k1", 11, 1);
+        assert_eq!(
+            vec![
+                "offset 1 of k1 given to both msg 10 and msg 11",
+                "k1 got offset 1 after an earlier send got 1",
+            ],
+            texts(&history)
+        );
+    }
+
+    #[test]
+    fn test_polls() {
+        let mut history = History::default();
+        send(&mut history, "k1", 10, 0);
+        send(&mut history, "k1", 11, 1);
+        send(&mut history, "k1", 12, 2);
+        poll(&mut history, "k1", 0, vec![[0, 10], [2, 12]]);
+        poll(&mut history, "k1", 1, vec![[1, 99]]);
+
+        assert_eq!(
+            vec![
+                "poll of k1 skipped acknowledged msg 11 at offset 1",
+                "poll of k1 returned msg 99 at offset 1, where msg 11 was acknowledged",
+            ],
+            texts(&history)
+        );
+    }
+
+    fn commit(history: &mut History, key: &str, offset: usize) {
+        let op = history.invoke(
+            "c1",
+            "n0",
+            BodyKind::CommitOffsets {
+                offsets: HashMap::from([(key.to_string(), offset)]),
+            },
+        );
+        history.complete(op, BodyKind::CommitOffsetsOk);
+    }
+
+    #[test]
+    fn test_polls_skip_committed() {
+        let mut history = History::default();
+        // the send of msg 11 was never acknowledged, but a poll saw it
+        let op = history.invoke(
+            "c1",
+            "n0",
+            BodyKind::Send {
+                key: "k1".to_string(),
+                msg: 11,
+            },
+        );
+        send(&mut history, "k1", 10, 0);
+        poll(&mut history, "k1", 0, vec![[0, 10], [1, 11], [2, 12]]);
+        commit(&mut history, "k1", 1);
+
+        // offset 2 was seen but not committed
+        poll(&mut history, "k1", 0, vec![[0, 10], [3, 13]]);
+        poll(&mut history, "k1", 2, vec![[2, 12]]);
+        history.complete(op, BodyKind::SendOk { offset: 1 });
+
+        assert_eq!(
+            vec!["poll of k1 skipped committed msg 11 at offset 1"],
+            texts(&history)
+        );
+    }
+
+    #[test]
+    fn test_commits_go_backwards() {
+        let mut history = History::default();
+        let commit = history.invoke(
+            "c1",
+            "n0",
+            BodyKind::CommitOffsets {
+                offsets: HashMap::from([("k1".to_string(), 5)]),
+            },
+        );
+        history.complete(commit, BodyKind::CommitOffsetsOk);
+        let list = history.invoke(
+            "c1",
+            "n1",
+            BodyKind::ListCommittedOffsets {
+                keys: vec!["k1".to_string()],
+            },
+        );
+        history.complete(
+            list,
+            BodyKind::ListCommittedOffsetsOk {
+                offsets: HashMap::from([("k1".to_string(), 3)]),
+            },
+        );
+
+        assert_eq!(
+            vec!["k1 was committed at 5 but listed at Some(3)"],
+            texts(&history)
+        );
+    }
+}
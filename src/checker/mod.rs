@@ -0,0 +1,194 @@
+pub mod broadcast;
+pub mod kafka;
+pub mod unique_id;
+
+use std::{collections::HashMap, fmt};
+
+use crate::message::{is_client, BodyKind, Message};
+
+/**
+ * one client request and the reply to it, if any came back
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Op {
+    pub client: String,
+    pub node: String,
+    pub request: BodyKind,
+    /// None while the op is in flight, or if it timed out
+    pub reply: Option<BodyKind>,
+    /// logical time the request was sent
+    pub invoked: usize,
+    /// logical time the reply came back
+    pub completed: Option<usize>,
+}
+
+impl Op {
+    /**
+     * the node replied and the reply is not an error
+     */
+    pub fn is_ok(&self) -> bool {
+        matches!(&self.reply, Some(kind) if !matches!(kind, BodyKind::Error { .. }))
+    }
+
+    /**
+     * self completed before other was invoked, so other must observe self
+     */
+    pub fn precedes(&self, other: &Op) -> bool {
+        matches!(self.completed, Some(completed) if completed < other.invoked)
+    }
+}
+
+/**
+ * client operations in the order they were invoked.
+ * it is recorded by the in-process harness or parsed from node logs
+ */
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    ops: Vec<Op>,
+    /// op index of requests waiting for a reply, by (client, msg_id)
+    pending: HashMap<(String, usize), usize>,
+    clock: usize,
+}
+
+impl History {
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    fn tick(&mut self) -> usize {
+        self.clock += 1;
+        self.clock
+    }
+
+    /**
+     * start an op, return its index for `complete`
+     */
+    pub fn invoke(&mut self, client: &str, node: &str, request: BodyKind) -> usize {
+        let invoked = self.tick();
+        self.ops.push(Op {
+            client: client.to_string(),
+            node: node.to_string(),
+            request,
+            reply: None,
+            invoked,
+            completed: None,
+        });
+        self.ops.len() - 1
+    }
+
+    pub fn complete(&mut self, op: usize, reply: BodyKind) {
+        let completed = self.tick();
+        let op = &mut self.ops[op];
+        if op.completed.is_none() {
+            op.reply = Some(reply);
+            op.completed = Some(completed);
+        }
+    }
+
+    /**
+     * record a message seen on the wire.
+     * requests from a client start an op and replies to a client complete it,
+     * anything else, like traffic between nodes, is ignored
+     */
+    pub fn record(&mut self, msg: &Message) {
+        if is_client(&msg.src) && !is_client(&msg.dst) {
+            let key = (msg.src.clone(), msg.body.msg_id);
+            if !self.pending.contains_key(&key) {
+                let op = self.invoke(&msg.src, &msg.dst, msg.body.kind.clone());
+                self.pending.insert(key, op);
+            }
+        } else if is_client(&msg.dst) {
+            let Some(reply_to) = msg.body.reply_to else {
+                return;
+            };
+            if let Some(op) = self.pending.remove(&(msg.dst.clone(), reply_to)) {
+                self.complete(op, msg.body.kind.clone());
+            }
+        }
+    }
+
+    /**
+     * build a history from node logs, e.g. maelstrom's `node-logs` files written
+     * with `DIST_SYS_LOG=debug`. every line holding a message after its first `{`
+     * is recorded, other lines are skipped.
+     * logs of several nodes must be merged in time order first
+     */
+    pub fn parse(logs: &str) -> Self {
+        let mut history = History::default();
+        for line in logs.lines() {
+            let Some(start) = line.find('{') else {
+                continue;
+            };
+            if let Ok(msg) = serde_json::from_str::<Message>(&line[start..]) {
+                history.record(&msg);
+            }
+        }
+        history
+    }
+}
+
+/**
+ * a rule broken by a history
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// index of the op breaking the rule, if a single one does
+    pub op: Option<usize>,
+    pub text: String,
+}
+
+impl Violation {
+    pub fn new(op: Option<usize>, text: impl Into<String>) -> Self {
+        Self {
+            op,
+            text: text.into(),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.op {
+            Some(op) => write!(f, "op {}: {}", op, self.text),
+            None => write!(f, "{}", self.text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::BodyKind;
+
+    use super::History;
+
+    #[test]
+    fn test_parse_logs() {
+        let logs = r#"
+1700000000.001 DEBUG [n0] recv {"src":"c1","dest":"n0","body":{"type":"broadcast","message":3,"msg_id":1}}
+1700000000.002 DEBUG [n0] send {"src":"n0","dest":"n1","body":{"type":"broadcast","message":3,"msg_id":1}}
+1700000000.003 DEBUG [n0] send {"src":"n0","dest":"c1","body":{"type":"broadcast_ok","msg_id":2,"in_reply_to":1}}
+1700000000.004 DEBUG [n1] recv {"src":"c1","dest":"n1","body":{"type":"read","msg_id":2}}
+1700000000.005 INFO  [n1] stats {"received":2,"sent":0,"rejected":0}
+"#;
+        let history = History::parse(logs);
+        assert_eq!(2, history.len());
+
+        let broadcast = &history.ops()[0];
+        assert_eq!("n0", broadcast.node);
+        assert_eq!(BodyKind::Broadcast { message: 3 }, broadcast.request);
+        assert!(broadcast.is_ok());
+
+        let read = &history.ops()[1];
+        assert_eq!(None, read.reply);
+        assert!(broadcast.precedes(read));
+        assert!(!read.precedes(broadcast));
+    }
+}
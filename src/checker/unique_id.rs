@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+use crate::message::BodyKind;
+
+use super::{History, Violation};
+
+/**
+ * no two acknowledged generates, on any node, return the same id
+ */
+pub fn check(history: &History) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut owners: HashMap<&str, usize> = HashMap::new();
+
+    for (i, op) in history.ops().iter().enumerate() {
+        let Some(BodyKind::GenerateOk { id }) = &op.reply else {
+            continue;
+        };
+        if let Some(first) = owners.insert(id, i) {
+            violations.push(Violation::new(
+                Some(i),
+                format!(
+                    "id {} from {} was already given by {} to op {}",
+                    id,
+                    op.node,
+                    history.ops()[first].node,
+                    first
+                ),
+            ));
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{checker::History, message::BodyKind};
+
+    use super::check;
+
+    fn generate(history: &mut History, node: &str, id: &str) {
+        let op = history.invoke("c1", node, BodyKind::Generate);
+        history.complete(op, BodyKind::GenerateOk { id: id.to_string() });
+    }
+
+    #[test]
+    fn test_duplicate_id() {
+        let mut history = History::default();
+        generate(&mut history, "n0", "n0-1");
+        generate(&mut history, "n1", "n1-1");
+        assert!(check(&history).is_empty());
+
+        generate(&mut history, "n1", "n0-1");
+        let violations = check(&history);
+        assert_eq!(1, violations.len());
+        assert_eq!(Some(2), violations[0].op);
+        assert_eq!(
+            "op 2: id n0-1 from n1 was already given by n0 to op 0",
+            violations[0].to_string()
+        );
+    }
+}
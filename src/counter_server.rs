@@ -0,0 +1,371 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    crdt::{Crdt, GCounter, PNCounter},
+    error::{self, Error},
+    kv::KvClient,
+    log_warn,
+    message::{Body, BodyKind, Message},
+    server::{HasInner, Serve, ServerInner},
+};
+
+/// how often the whole counter is pushed to every peer
+const REPLICATE_INTERVAL: Duration = Duration::from_millis(200);
+
+/**
+ * a counter crdt a node can serve, adding only to its own slots
+ */
+pub trait Counter: Crdt + Debug + Send + 'static {
+    type Value: Serialize;
+
+    /// workload name, used in errors and kv keys
+    const NAME: &'static str;
+
+    /**
+     * add delta through node_id, fails if the counter can't take it
+     */
+    fn add(&mut self, node_id: &str, delta: i64) -> Result<(), String>;
+
+    fn value(&self) -> Self::Value;
+
+    /**
+     * the counter with only what was added through node_id
+     */
+    fn slot(&self, node_id: &str) -> Self;
+}
+
+impl Counter for GCounter {
+    type Value = u64;
+
+    const NAME: &'static str = "g-counter";
+
+    fn add(&mut self, node_id: &str, delta: i64) -> Result<(), String> {
+        let delta = u64::try_from(delta)
+            .map_err(|_| format!("a g-counter only grows, got delta {}", delta))?;
+        self.increment(node_id, delta);
+        Ok(())
+    }
+
+    fn value(&self) -> u64 {
+        GCounter::value(self)
+    }
+
+    fn slot(&self, node_id: &str) -> Self {
+        GCounter::slot(self, node_id)
+    }
+}
+
+impl Counter for PNCounter {
+    type Value = i64;
+
+    const NAME: &'static str = "pn-counter";
+
+    fn add(&mut self, node_id: &str, delta: i64) -> Result<(), String> {
+        self.increment(node_id, delta);
+        Ok(())
+    }
+
+    fn value(&self) -> i64 {
+        PNCounter::value(self)
+    }
+
+    fn slot(&self, node_id: &str) -> Self {
+        PNCounter::slot(self, node_id)
+    }
+}
+
+/**
+ * a state based counter.
+ * each node only adds to its own slots, merging takes the max of every slot,
+ * so merges can be repeated, reordered or lost and the nodes still converge
+ */
+#[derive(Debug, Default)]
+pub struct CounterServer<C: Counter> {
+    inner: ServerInner,
+    pub counter: Mutex<C>,
+    /// where slots are stored besides gossip, so nodes still converge while cut off from each other
+    kv: Option<KvClient>,
+}
+
+impl<C: Counter> CounterServer<C> {
+    pub fn with_kv(kv: KvClient) -> Self {
+        Self {
+            kv: Some(kv),
+            ..Default::default()
+        }
+    }
+
+    fn reply_to(&self, msg: &Message, kind: BodyKind) -> Message {
+        let body = Body {
+            kind,
+            msg_id: self.inner.next_id(),
+            reply_to: Some(msg.body.msg_id),
+        };
+
+        Message {
+            src: self.inner.node_id().to_string(),
+            dst: msg.src.to_string(),
+            body,
+        }
+    }
+
+    pub fn add(&self, msg: &Message, delta: i64) -> error::Result<Message> {
+        self.counter
+            .lock()
+            .unwrap()
+            .add(self.inner.node_id(), delta)
+            .map_err(Error::MalformedRequest)?;
+
+        Ok(self.reply_to(msg, BodyKind::AddOk))
+    }
+
+    pub fn value(&self) -> C::Value {
+        self.counter.lock().unwrap().value()
+    }
+
+    pub fn read(&self, msg: &Message) -> Message {
+        let kind = BodyKind::ReadOk {
+            messages: None,
+            version: None,
+            value: Some(json!(self.value())),
+        };
+        self.reply_to(msg, kind)
+    }
+
+    /**
+     * take the max of every slot, a stale state changes nothing
+     */
+    pub fn merge(&self, state: &Value) -> error::Result<()> {
+        let other: C = serde_json::from_value(state.clone()).map_err(|err| {
+            Error::MalformedRequest(format!("invalid {} state: {}", C::NAME, err))
+        })?;
+
+        self.counter.lock().unwrap().merge(&other);
+        Ok(())
+    }
+
+    pub fn replicate(&self) -> Vec<Message> {
+        let state = json!(*self.counter.lock().unwrap());
+        self.inner
+            .peers()
+            .into_iter()
+            .map(|peer| Message {
+                src: self.inner.node_id().to_string(),
+                dst: peer.to_string(),
+                body: Body {
+                    kind: BodyKind::Replicate {
+                        state: state.clone(),
+                    },
+                    msg_id: self.inner.next_id(),
+                    reply_to: None,
+                },
+            })
+            .collect()
+    }
+
+    /**
+     * write this node's slots and merge the slots of its peers.
+     * only the owner writes its slots and they only grow, so a plain write is safe
+     */
+    pub async fn sync(&self, kv: &KvClient) -> error::Result<()> {
+        let node_id = self.inner.node_id();
+        let slot = self.counter.lock().unwrap().slot(node_id);
+        kv.write(&self.inner, slot_key::<C>(node_id), slot).await?;
+
+        for peer in self.inner.peers() {
+            let other: C = match kv.read(&self.inner, slot_key::<C>(peer)).await {
+                Ok(other) => other,
+                Err(Error::KeyDoesNotExist(_)) => continue,
+                Err(err) => return Err(err),
+            };
+            self.counter.lock().unwrap().merge(&other);
+        }
+        Ok(())
+    }
+}
+
+fn slot_key<C: Counter>(node_id: &str) -> String {
+    format!("{}/{}", C::NAME, node_id)
+}
+
+#[async_trait]
+impl<C: Counter> Serve for CounterServer<C> {
+    async fn reply(&self, msg: &Message) -> error::Result<Option<Message>> {
+        match &msg.body.kind {
+            BodyKind::Init { .. } => Ok(self.inner.init(msg)),
+            BodyKind::Add { delta } => self.add(msg, *delta).map(Some),
+            BodyKind::Read { .. } => Ok(Some(self.read(msg))),
+            BodyKind::Replicate { state } => self.merge(state).map(|_| None),
+            _ => Err(Error::NotSupported(format!("cannot handle msg: {:?}", msg))),
+        }
+    }
+
+    fn schedule(self: Arc<Self>) {
+        let server = self.clone();
+        self.inner.timers().every(REPLICATE_INTERVAL, move || {
+            let server = server.clone();
+            async move {
+                for msg in server.replicate() {
+                    server.inner.send(msg);
+                }
+                if let Some(kv) = server.kv.clone() {
+                    // a simulation runs timers inline with deliveries, don't wait for the replies here
+                    tokio::spawn(async move {
+                        if let Err(err) = server.sync(&kv).await {
+                            log_warn!(
+                                server.inner.node_id(),
+                                "sync with {}: {}",
+                                kv.service(),
+                                err
+                            );
+                        }
+                    });
+                }
+            }
+        });
+    }
+}
+
+impl<C: Counter> HasInner for CounterServer<C> {
+    fn as_inner(&self) -> &ServerInner {
+        &self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::{
+        crdt::{GCounter, PNCounter},
+        error::Error,
+        harness::{Cluster, SimConfig, Simulation},
+        kv::KvClient,
+        message::BodyKind,
+    };
+
+    use super::{Counter, CounterServer};
+
+    fn read_ok(value: i64) -> BodyKind {
+        BodyKind::ReadOk {
+            messages: None,
+            version: None,
+            value: Some(json!(value)),
+        }
+    }
+
+    fn read() -> BodyKind {
+        BodyKind::Read {
+            key: None,
+            since: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_and_converge() {
+        let mut cluster: Cluster<CounterServer<GCounter>> = Cluster::new(3).await;
+        let client = cluster.client();
+        for i in 0..9 {
+            let node_id = format!("n{}", i % 3);
+            let reply_msg = client
+                .request(&node_id, BodyKind::Add { delta: i })
+                .await
+                .unwrap();
+            assert_eq!(BodyKind::AddOk, reply_msg.body.kind);
+        }
+
+        let res = client.request("n0", BodyKind::Add { delta: -1 }).await;
+        assert!(matches!(res, Err(Error::MalformedRequest(_))));
+
+        let converged = cluster
+            .wait_until(Duration::from_secs(2), |cluster| {
+                cluster.nodes().all(|(_, node)| node.value() == 36)
+            })
+            .await;
+        assert!(converged);
+
+        let reply_msg = client.request("n2", read()).await.unwrap();
+        assert_eq!(read_ok(36), reply_msg.body.kind);
+    }
+
+    /**
+     * add deltas[i] through node i % 3 while n0 is cut off, then heal
+     */
+    async fn converge_after_partition<C: Counter>(seed: u64, deltas: &[i64]) {
+        let config = SimConfig::new(seed)
+            .latency(1, 50)
+            .drop_rate(0.2)
+            .duplicate_rate(0.2);
+        let mut sim: Simulation<CounterServer<C>> = Simulation::new(3, config).await;
+
+        sim.partition(&[&["n0"], &["n1", "n2"]]);
+        for (i, delta) in deltas.iter().enumerate() {
+            let node_id = format!("n{}", i % 3);
+            let kind = BodyKind::Add { delta: *delta };
+            assert_eq!(
+                BodyKind::AddOk,
+                sim.call(&node_id, kind).await.unwrap().body.kind
+            );
+        }
+        sim.run_for(1_000).await;
+        let total: i64 = deltas.iter().sum();
+        let n0: i64 = deltas.iter().step_by(3).sum();
+        assert_eq!(json!(n0), json!(sim.node("n0").value()));
+        assert_eq!(json!(total - n0), json!(sim.node("n1").value()));
+
+        sim.heal();
+        sim.run_for(2_000).await;
+        for node_id in sim.node_ids() {
+            let reply_msg = sim.call(&node_id, read()).await;
+            assert_eq!(read_ok(total), reply_msg.unwrap().body.kind);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_g_counter_converge_after_partition() {
+        converge_after_partition::<GCounter>(4, &[1, 2, 3, 4, 5, 6]).await;
+    }
+
+    #[tokio::test]
+    async fn test_pn_counter_converge_after_partition() {
+        converge_after_partition::<PNCounter>(5, &[1, -2, 3, -4, 5, -6]).await;
+    }
+
+    #[tokio::test]
+    async fn test_converge_through_kv() {
+        let nodes = (0..3)
+            .map(|_| CounterServer::<GCounter>::with_kv(KvClient::seq()))
+            .collect();
+        let mut sim = Simulation::with_nodes(nodes, SimConfig::new(6)).await;
+
+        // nodes can't gossip at all, they only meet in seq-kv
+        sim.partition(&[&["n0"], &["n1"], &["n2"]]);
+        for i in 1..=6 {
+            let node_id = format!("n{}", i % 3);
+            sim.call(&node_id, BodyKind::Add { delta: i })
+                .await
+                .unwrap();
+        }
+
+        // syncs wait for seq-kv on spawned tasks, run until their replies landed
+        let converged = sim
+            .run_until(10_000, |sim| {
+                sim.nodes().all(|(_, node)| node.value() == 21)
+            })
+            .await;
+        assert!(converged);
+        assert_eq!(
+            Some(json!({"n2": 5 + 2})),
+            sim.kv("seq-kv").get("g-counter/n2")
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Crdt;
+
+/**
+ * grow-only counter, one slot per node.
+ * a node only adds to its own slot, merge keeps the max of every slot
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter {
+    counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn increment(&mut self, node_id: &str, delta: u64) {
+        *self.counts.entry(node_id.to_string()).or_default() += delta;
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /**
+     * total added through one node
+     */
+    pub fn get(&self, node_id: &str) -> u64 {
+        self.counts.get(node_id).copied().unwrap_or_default()
+    }
+
+    /**
+     * the counter with only the slot of node_id
+     */
+    pub fn slot(&self, node_id: &str) -> Self {
+        let counts = self
+            .counts
+            .get_key_value(node_id)
+            .map(|(node_id, count)| (node_id.clone(), *count))
+            .into_iter()
+            .collect();
+        Self { counts }
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node_id, other) in other.counts.iter() {
+            let count = self.counts.entry(node_id.clone()).or_default();
+            *count = (*count).max(*other);
+        }
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        let counts = self
+            .counts
+            .iter()
+            .filter(|(node_id, count)| since.counts.get(*node_id) < Some(count))
+            .map(|(node_id, count)| (node_id.clone(), *count))
+            .collect();
+        Self { counts }
+    }
+}
+
+/**
+ * counter which goes both ways, a g-counter for increments and one for decrements
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    p: GCounter,
+    n: GCounter,
+}
+
+impl PNCounter {
+    pub fn increment(&mut self, node_id: &str, delta: i64) {
+        if delta >= 0 {
+            self.p.increment(node_id, delta as u64);
+        } else {
+            self.n.increment(node_id, delta.unsigned_abs());
+        }
+    }
+
+    pub fn value(&self) -> i64 {
+        self.p.value() as i64 - self.n.value() as i64
+    }
+
+    /**
+     * the counter with only the slots of node_id
+     */
+    pub fn slot(&self, node_id: &str) -> Self {
+        Self {
+            p: self.p.slot(node_id),
+            n: self.n.slot(node_id),
+        }
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: &Self) {
+        self.p.merge(&other.p);
+        self.n.merge(&other.n);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self {
+            p: self.p.delta(&since.p),
+            n: self.n.delta(&since.n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use crate::crdt::{laws, Crdt};
+
+    use super::{GCounter, PNCounter};
+
+    #[test]
+    fn test_g_counter() {
+        let mut a = GCounter::default();
+        let mut b = GCounter::default();
+        a.increment("n0", 3);
+        b.increment("n1", 4);
+        b.increment("n0", 1);
+        a.merge(&b);
+        assert_eq!(7, a.value());
+        assert_eq!(r#"{"n0":3,"n1":4}"#, serde_json::to_string(&a).unwrap());
+        assert_eq!(4, a.slot("n1").value());
+        assert_eq!(GCounter::default(), a.slot("n2"));
+
+        laws::check(1, |counter: &mut GCounter, node_id, rng| {
+            counter.increment(node_id, rng.gen_range(0..10))
+        });
+    }
+
+    #[test]
+    fn test_pn_counter() {
+        let mut a = PNCounter::default();
+        a.increment("n0", 5);
+        a.increment("n0", -7);
+        let mut b = PNCounter::default();
+        b.increment("n1", -1);
+        a.merge(&b);
+        assert_eq!(-3, a.value());
+        assert_eq!(-2, a.slot("n0").value());
+
+        laws::check(2, |counter: &mut PNCounter, node_id, rng| {
+            counter.increment(node_id, rng.gen_range(-10..10))
+        });
+    }
+}
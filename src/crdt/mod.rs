@@ -0,0 +1,92 @@
+pub mod counter;
+pub mod register;
+pub mod set;
+
+pub use counter::{GCounter, PNCounter};
+pub use register::LWWRegister;
+pub use set::{GSet, ORSet, TwoPSet};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/**
+ * a state based replicated data type.
+ * merge is commutative, associative and idempotent, so replicas can gossip
+ * their state in any order, any number of times, and still converge
+ */
+pub trait Crdt: Clone + Default + Serialize + DeserializeOwned {
+    /**
+     * fold the state of another replica into this one
+     */
+    fn merge(&mut self, other: &Self);
+
+    /**
+     * the part of self which `since` is missing.
+     * merging it into `since` gives the same state as merging all of self,
+     * so a replica only needs to send what its peer hasn't seen
+     */
+    fn delta(&self, since: &Self) -> Self;
+}
+
+/**
+ * merge laws checked on states reached by random operations and merges
+ */
+#[cfg(test)]
+pub(crate) mod laws {
+    use std::fmt::Debug;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::Crdt;
+
+    pub const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+    fn merged<C: Crdt>(a: &C, b: &C) -> C {
+        let mut c = a.clone();
+        c.merge(b);
+        c
+    }
+
+    /**
+     * run random ops on a few replicas which merge now and then,
+     * and check the laws on every triple of the states they go through
+     */
+    pub fn check<C, F>(seed: u64, op: F)
+    where
+        C: Crdt + PartialEq + Debug,
+        F: Fn(&mut C, &str, &mut StdRng),
+    {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut replicas: Vec<C> = NODES.iter().map(|_| C::default()).collect();
+        let mut states = Vec::new();
+
+        for _ in 0..200 {
+            let i = rng.gen_range(0..NODES.len());
+            if rng.gen_bool(0.3) {
+                let j = rng.gen_range(0..NODES.len());
+                let other = replicas[j].clone();
+                replicas[i].merge(&other);
+            } else {
+                op(&mut replicas[i], NODES[i], &mut rng);
+            }
+            states.push(replicas[i].clone());
+        }
+
+        for _ in 0..200 {
+            let a = &states[rng.gen_range(0..states.len())];
+            let b = &states[rng.gen_range(0..states.len())];
+            let c = &states[rng.gen_range(0..states.len())];
+
+            assert_eq!(merged(a, b), merged(b, a), "commutative");
+            assert_eq!(
+                merged(&merged(a, b), c),
+                merged(a, &merged(b, c)),
+                "associative"
+            );
+            assert_eq!(*a, merged(a, a), "idempotent");
+            assert_eq!(merged(b, a), merged(b, &a.delta(b)), "delta");
+
+            let json = serde_json::to_string(a).unwrap();
+            assert_eq!(*a, serde_json::from_str::<C>(&json).unwrap(), "serde");
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::Crdt;
+
+/**
+ * last-writer-wins register.
+ * the write with the larger timestamp wins, ties are broken by node id
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: DeserializeOwned"))]
+pub struct LWWRegister<T> {
+    value: Option<T>,
+    timestamp: u64,
+    node_id: String,
+}
+
+impl<T> Default for LWWRegister<T> {
+    fn default() -> Self {
+        Self {
+            value: None,
+            timestamp: 0,
+            node_id: String::new(),
+        }
+    }
+}
+
+impl<T> LWWRegister<T> {
+    /**
+     * a write older than the current one is ignored
+     */
+    pub fn set(&mut self, node_id: &str, timestamp: u64, value: T) {
+        if (timestamp, node_id) > (self.timestamp, self.node_id.as_str()) {
+            self.value = Some(value);
+            self.timestamp = timestamp;
+            self.node_id = node_id.to_string();
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn newer_than(&self, other: &Self) -> bool {
+        (self.timestamp, &self.node_id) > (other.timestamp, &other.node_id)
+    }
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> Crdt for LWWRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        if other.newer_than(self) {
+            *self = other.clone();
+        }
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        if self.newer_than(since) {
+            self.clone()
+        } else {
+            Self::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use crate::crdt::{laws, Crdt};
+
+    use super::LWWRegister;
+
+    #[test]
+    fn test_lww_register() {
+        let mut a = LWWRegister::default();
+        a.set("n0", 2, "a".to_string());
+        let mut b = LWWRegister::default();
+        b.set("n1", 1, "b".to_string());
+        b.merge(&a);
+        assert_eq!(Some("a"), b.get().map(String::as_str));
+
+        // same timestamp, the larger node id wins
+        a.set("n1", 2, "c".to_string());
+        assert_eq!(Some("c"), a.get().map(String::as_str));
+        a.set("n0", 2, "d".to_string());
+        assert_eq!(Some("c"), a.get().map(String::as_str));
+
+        laws::check(6, |register: &mut LWWRegister<usize>, node_id, rng| {
+            let timestamp = register.timestamp() + rng.gen_range(0..3);
+            register.set(node_id, timestamp, rng.gen_range(0..100));
+        });
+    }
+}
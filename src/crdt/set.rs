@@ -0,0 +1,307 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{counter::GCounter, Crdt};
+
+/**
+ * grow-only set, merge is the union
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(deserialize = "T: Ord + DeserializeOwned"))]
+pub struct GSet<T: Ord> {
+    elements: BTreeSet<T>,
+}
+
+impl<T: Ord> Default for GSet<T> {
+    fn default() -> Self {
+        Self {
+            elements: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord> GSet<T> {
+    /**
+     * return false if elem is already in the set
+     */
+    pub fn insert(&mut self, elem: T) -> bool {
+        self.elements.insert(elem)
+    }
+
+    pub fn contains(&self, elem: &T) -> bool {
+        self.elements.contains(elem)
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements.iter()
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> Crdt for GSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.elements.extend(other.elements.iter().cloned());
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self {
+            elements: self.elements.difference(&since.elements).cloned().collect(),
+        }
+    }
+}
+
+/**
+ * set with removal, a removed element can never come back
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: Ord + DeserializeOwned"))]
+pub struct TwoPSet<T: Ord> {
+    added: GSet<T>,
+    removed: GSet<T>,
+}
+
+impl<T: Ord> Default for TwoPSet<T> {
+    fn default() -> Self {
+        Self {
+            added: GSet::default(),
+            removed: GSet::default(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> TwoPSet<T> {
+    pub fn insert(&mut self, elem: T) {
+        self.added.insert(elem);
+    }
+
+    /**
+     * only an element in the set can be removed
+     */
+    pub fn remove(&mut self, elem: &T) -> bool {
+        if !self.contains(elem) {
+            return false;
+        }
+        self.removed.insert(elem.clone())
+    }
+
+    pub fn contains(&self, elem: &T) -> bool {
+        self.added.contains(elem) && !self.removed.contains(elem)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.added
+            .iter()
+            .filter(|elem| !self.removed.contains(elem))
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> Crdt for TwoPSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.added.merge(&other.added);
+        self.removed.merge(&other.removed);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self {
+            added: self.added.delta(&since.added),
+            removed: self.removed.delta(&since.removed),
+        }
+    }
+}
+
+/**
+ * unique tag of one insert, the node which made it and its count of inserts
+ */
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Dot {
+    pub node_id: String,
+    pub seq: u64,
+}
+
+/**
+ * observed-remove set, an element can be removed and added again.
+ * every insert gets a new tag and a remove only hides the tags it has seen,
+ * so an insert concurrent with a remove wins
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: Ord + DeserializeOwned"))]
+pub struct ORSet<T: Ord> {
+    /// live tags of every element in the set
+    entries: BTreeMap<T, BTreeSet<Dot>>,
+    /// tags of removed inserts
+    tombstones: BTreeSet<Dot>,
+    /// inserts made by each node
+    clock: GCounter,
+}
+
+impl<T: Ord> Default for ORSet<T> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            tombstones: BTreeSet::new(),
+            clock: GCounter::default(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> ORSet<T> {
+    pub fn insert(&mut self, node_id: &str, elem: T) {
+        self.clock.increment(node_id, 1);
+        let dot = Dot {
+            node_id: node_id.to_string(),
+            seq: self.clock.get(node_id),
+        };
+        self.entries.entry(elem).or_default().insert(dot);
+    }
+
+    /**
+     * hide every insert of elem seen so far
+     */
+    pub fn remove(&mut self, elem: &T) -> bool {
+        match self.entries.remove(elem) {
+            Some(dots) => {
+                self.tombstones.extend(dots);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, elem: &T) -> bool {
+        self.entries.contains_key(elem)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.entries.keys()
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> Crdt for ORSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.tombstones.extend(other.tombstones.iter().cloned());
+        for (elem, dots) in other.entries.iter() {
+            self.entries
+                .entry(elem.clone())
+                .or_default()
+                .extend(dots.iter().cloned());
+        }
+        let tombstones = &self.tombstones;
+        self.entries.retain(|_, dots| {
+            dots.retain(|dot| !tombstones.contains(dot));
+            !dots.is_empty()
+        });
+        self.clock.merge(&other.clock);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(elem, dots)| {
+                let seen = since.entries.get(elem);
+                let dots: BTreeSet<Dot> = dots
+                    .iter()
+                    .filter(|dot| !seen.is_some_and(|seen| seen.contains(dot)))
+                    .cloned()
+                    .collect();
+                (!dots.is_empty()).then(|| (elem.clone(), dots))
+            })
+            .collect();
+
+        Self {
+            entries,
+            tombstones: self
+                .tombstones
+                .difference(&since.tombstones)
+                .cloned()
+                .collect(),
+            clock: self.clock.delta(&since.clock),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use crate::crdt::{laws, Crdt};
+
+    use super::{GSet, ORSet, TwoPSet};
+
+    #[test]
+    fn test_g_set() {
+        let mut a = GSet::default();
+        a.insert(1);
+        let mut b = GSet::default();
+        b.insert(2);
+        a.merge(&b);
+        assert_eq!(vec![&1, &2], a.iter().collect::<Vec<_>>());
+
+        laws::check(3, |set: &mut GSet<usize>, _, rng| {
+            set.insert(rng.gen_range(0..20));
+        });
+    }
+
+    #[test]
+    fn test_two_p_set() {
+        let mut a = TwoPSet::default();
+        a.insert(1);
+        let mut b = a.clone();
+        assert!(b.remove(&1));
+        a.merge(&b);
+        a.insert(1);
+        assert!(!a.contains(&1));
+
+        laws::check(4, |set: &mut TwoPSet<usize>, _, rng| {
+            let elem = rng.gen_range(0..20);
+            if rng.gen_bool(0.3) {
+                set.remove(&elem);
+            } else {
+                set.insert(elem);
+            }
+        });
+    }
+
+    #[test]
+    fn test_or_set() {
+        let mut a = ORSet::default();
+        a.insert("n0", "x".to_string());
+        let mut b = a.clone();
+        b.remove(&"x".to_string());
+        // added again concurrently with the remove, the add wins
+        a.insert("n0", "x".to_string());
+        a.merge(&b);
+        assert!(a.contains(&"x".to_string()));
+
+        b.merge(&a);
+        b.remove(&"x".to_string());
+        a.merge(&b);
+        assert!(a.is_empty());
+
+        laws::check(5, |set: &mut ORSet<usize>, node_id, rng| {
+            let elem = rng.gen_range(0..10);
+            if rng.gen_bool(0.3) {
+                set.remove(&elem);
+            } else {
+                set.insert(node_id, elem);
+            }
+        });
+    }
+}
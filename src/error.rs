@@ -0,0 +1,199 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/**
+ * error codes defined by maelstrom.
+ * codes 0-999 are reserved by maelstrom, others are custom codes
+ * see https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "u32", into = "u32")]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    Custom(u32),
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> u32 {
+        match self {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Custom(code) => *code,
+        }
+    }
+
+    /**
+     * a definite error means the operation certainly did not take place,
+     * otherwise it may or may not have happened
+     */
+    pub fn is_definite(&self) -> bool {
+        !matches!(
+            self,
+            ErrorCode::Timeout | ErrorCode::Crash | ErrorCode::Custom(_)
+        )
+    }
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            code => ErrorCode::Custom(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        code.code()
+    }
+}
+
+/**
+ * error returned by handlers, each variant carries a human readable text.
+ * the serve loop turns it into an `error` reply to the requester
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Timeout(String),
+    NodeNotFound(String),
+    NotSupported(String),
+    TemporarilyUnavailable(String),
+    MalformedRequest(String),
+    Crash(String),
+    Abort(String),
+    KeyDoesNotExist(String),
+    KeyAlreadyExists(String),
+    PreconditionFailed(String),
+    TxnConflict(String),
+    Custom(u32, String),
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        let text = text.into();
+        match code {
+            ErrorCode::Timeout => Error::Timeout(text),
+            ErrorCode::NodeNotFound => Error::NodeNotFound(text),
+            ErrorCode::NotSupported => Error::NotSupported(text),
+            ErrorCode::TemporarilyUnavailable => Error::TemporarilyUnavailable(text),
+            ErrorCode::MalformedRequest => Error::MalformedRequest(text),
+            ErrorCode::Crash => Error::Crash(text),
+            ErrorCode::Abort => Error::Abort(text),
+            ErrorCode::KeyDoesNotExist => Error::KeyDoesNotExist(text),
+            ErrorCode::KeyAlreadyExists => Error::KeyAlreadyExists(text),
+            ErrorCode::PreconditionFailed => Error::PreconditionFailed(text),
+            ErrorCode::TxnConflict => Error::TxnConflict(text),
+            ErrorCode::Custom(code) => Error::Custom(code, text),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Timeout(_) => ErrorCode::Timeout,
+            Error::NodeNotFound(_) => ErrorCode::NodeNotFound,
+            Error::NotSupported(_) => ErrorCode::NotSupported,
+            Error::TemporarilyUnavailable(_) => ErrorCode::TemporarilyUnavailable,
+            Error::MalformedRequest(_) => ErrorCode::MalformedRequest,
+            Error::Crash(_) => ErrorCode::Crash,
+            Error::Abort(_) => ErrorCode::Abort,
+            Error::KeyDoesNotExist(_) => ErrorCode::KeyDoesNotExist,
+            Error::KeyAlreadyExists(_) => ErrorCode::KeyAlreadyExists,
+            Error::PreconditionFailed(_) => ErrorCode::PreconditionFailed,
+            Error::TxnConflict(_) => ErrorCode::TxnConflict,
+            Error::Custom(code, _) => ErrorCode::Custom(*code),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            Error::Timeout(text)
+            | Error::NodeNotFound(text)
+            | Error::NotSupported(text)
+            | Error::TemporarilyUnavailable(text)
+            | Error::MalformedRequest(text)
+            | Error::Crash(text)
+            | Error::Abort(text)
+            | Error::KeyDoesNotExist(text)
+            | Error::KeyAlreadyExists(text)
+            | Error::PreconditionFailed(text)
+            | Error::TxnConflict(text)
+            | Error::Custom(_, text) => text,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error {}: {}", self.code().code(), self.text())
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use crate::message::{Body, BodyKind};
+
+    use super::{Error, ErrorCode};
+
+    #[test]
+    fn test_error_code_roundtrip() {
+        for code in [0, 1, 10, 11, 12, 13, 14, 20, 21, 22, 30, 1000] {
+            assert_eq!(code, ErrorCode::from(code).code());
+        }
+        assert_eq!(ErrorCode::Custom(1000), ErrorCode::from(1000));
+        assert!(!ErrorCode::Timeout.is_definite());
+        assert!(ErrorCode::KeyDoesNotExist.is_definite());
+    }
+
+    #[test]
+    fn test_error_body() {
+        let line = r#"{"type":"error","in_reply_to":5,"code":20,"text":"no such key"}"#;
+        let body: Body = serde_json::from_str(line).unwrap();
+        assert_eq!(Some(5), body.reply_to);
+        assert_eq!(
+            BodyKind::Error {
+                code: ErrorCode::KeyDoesNotExist,
+                text: "no such key".to_string()
+            },
+            body.kind
+        );
+
+        let err = Error::new(ErrorCode::KeyDoesNotExist, "no such key");
+        assert_eq!(Error::KeyDoesNotExist("no such key".to_string()), err);
+        assert_eq!("error 20: no such key", err.to_string());
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{self, Instant},
+};
+
+use super::KvService;
+use crate::{
+    checker::History,
+    error, log_debug,
+    message::{BodyKind, Message},
+    rpc::Rpc,
+    server::Serve,
+    stats::StatsSnapshot,
+};
+
+type Clients = Arc<Mutex<HashMap<String, Rpc>>>;
+type Services = BTreeMap<String, Arc<KvService>>;
+
+/**
+ * N nodes served in one process, wired together by an in-memory network.
+ * it plays maelstrom: initializes the nodes, routes every message
+ * between them, answers requests to its kv services and hands replies
+ * to the clients which requested them
+ */
+pub struct Cluster<S: Serve> {
+    nodes: BTreeMap<String, Arc<S>>,
+    services: Services,
+    net: mpsc::UnboundedSender<Message>,
+    clients: Clients,
+    history: Arc<Mutex<History>>,
+    next_client: usize,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl<S: Serve + Default> Cluster<S> {
+    /**
+     * start `count` default nodes named n0, n1, ...
+     */
+    pub async fn new(count: usize) -> Self {
+        Self::with_nodes((0..count).map(|_| S::default()).collect()).await
+    }
+}
+
+impl<S: Serve> Cluster<S> {
+    pub async fn with_nodes(nodes: Vec<S>) -> Self {
+        let nodes: BTreeMap<String, Arc<S>> = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(i, node)| (format!("n{}", i), Arc::new(node)))
+            .collect();
+        let services: Services = KvService::all()
+            .into_iter()
+            .map(|service| (service.name().to_string(), Arc::new(service)))
+            .collect();
+        let (net, inbound) = mpsc::unbounded_channel();
+        let clients: Clients = Arc::default();
+        let history: Arc<Mutex<History>> = Arc::default();
+
+        let mut tasks = Vec::with_capacity(nodes.len() + 1);
+        for node in nodes.values() {
+            let mut outbox = node
+                .as_inner()
+                .take_outbox()
+                .expect("outbox is taken, a node can only join one cluster");
+            let net = net.clone();
+            let stats = node.as_inner().stats().clone();
+            tasks.push(tokio::spawn(async move {
+                while let Some(msg) = outbox.recv().await {
+                    stats.record_sent(&msg);
+                    if net.send(msg).is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+        tasks.push(tokio::spawn(route(
+            inbound,
+            net.clone(),
+            nodes.clone(),
+            services.clone(),
+            clients.clone(),
+            history.clone(),
+        )));
+
+        let mut cluster = Self {
+            nodes,
+            services,
+            net,
+            clients,
+            history,
+            next_client: 0,
+            tasks,
+        };
+        cluster.init().await;
+        cluster
+    }
+
+    async fn init(&mut self) {
+        let client = self.client();
+        let node_ids = self.node_ids();
+        for node_id in node_ids.iter() {
+            let kind = BodyKind::Init {
+                node_id: node_id.clone(),
+                node_ids: node_ids.clone(),
+            };
+            let reply_msg = client
+                .request(node_id, kind)
+                .await
+                .unwrap_or_else(|err| panic!("failed to init {}: {}", node_id, err));
+            assert_eq!(BodyKind::InitOk, reply_msg.body.kind);
+        }
+    }
+
+    pub fn node_ids(&self) -> Vec<String> {
+        self.nodes.keys().cloned().collect()
+    }
+
+    pub fn node(&self, node_id: &str) -> &S {
+        &self.nodes[node_id]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (&String, &S)> {
+        self.nodes.iter().map(|(id, node)| (id, node.as_ref()))
+    }
+
+    /**
+     * the kv service named e.g. `seq-kv`
+     */
+    pub fn kv(&self, name: &str) -> &KvService {
+        &self.services[name]
+    }
+
+    /**
+     * a new client c0, c1, ... able to send requests to any node
+     */
+    pub fn client(&mut self) -> Client {
+        let id = format!("c{}", self.next_client);
+        self.next_client += 1;
+
+        let rpc = Rpc::new(self.net.clone());
+        self.clients.lock().unwrap().insert(id.clone(), rpc.clone());
+        Client { id, rpc }
+    }
+
+    /**
+     * counters of all nodes added up
+     */
+    pub fn stats(&self) -> StatsSnapshot {
+        self.nodes
+            .values()
+            .map(|node| node.as_inner().stats().snapshot())
+            .fold(StatsSnapshot::default(), |sum, stats| sum + stats)
+    }
+
+    /**
+     * every client request routed so far and the replies to them
+     */
+    pub fn history(&self) -> History {
+        self.history.lock().unwrap().clone()
+    }
+
+    /**
+     * poll `cond` until it holds or `timeout` passes, return whether it held
+     */
+    pub async fn wait_until<F>(&self, timeout: Duration, cond: F) -> bool
+    where
+        F: Fn(&Self) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if cond(self) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+impl<S: Serve> Drop for Cluster<S> {
+    fn drop(&mut self) {
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+        // timers hold their node, stopping them lets the nodes go
+        for node in self.nodes.values() {
+            node.as_inner().timers().stop();
+        }
+    }
+}
+
+/**
+ * deliver every message to its destination node, kv service or client,
+ * messages to unknown destinations are dropped
+ */
+async fn route<S: Serve>(
+    mut inbound: mpsc::UnboundedReceiver<Message>,
+    net: mpsc::UnboundedSender<Message>,
+    nodes: BTreeMap<String, Arc<S>>,
+    services: Services,
+    clients: Clients,
+    history: Arc<Mutex<History>>,
+) {
+    while let Some(msg) = inbound.recv().await {
+        history.lock().unwrap().record(&msg);
+        if let Some(node) = nodes.get(&msg.dst) {
+            node.as_inner().stats().record_received(&msg);
+            if !node.as_inner().resolve(&msg) {
+                tokio::spawn(node.clone().process(msg));
+            }
+            continue;
+        }
+        if let Some(service) = services.get(&msg.dst) {
+            // the receiver is the loop itself, it can't be gone
+            let _ = net.send(service.handle(&msg));
+            continue;
+        }
+
+        let client = clients.lock().unwrap().get(&msg.dst).cloned();
+        match client {
+            Some(rpc) => {
+                rpc.resolve(&msg);
+            }
+            None => log_debug!(&msg.src, "drop msg to unknown {}", msg.dst),
+        }
+    }
+}
+
+/**
+ * a maelstrom client inside the cluster
+ */
+#[derive(Debug, Clone)]
+pub struct Client {
+    id: String,
+    rpc: Rpc,
+}
+
+impl Client {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.rpc.set_timeout(timeout);
+    }
+
+    /**
+     * send kind to node and wait for its reply
+     */
+    pub fn request(
+        &self,
+        node_id: &str,
+        kind: BodyKind,
+    ) -> impl Future<Output = error::Result<Message>> + Send + 'static {
+        self.rpc.call(&self.id, node_id, kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use async_trait::async_trait;
+
+    use crate::{
+        error::{self, Error},
+        message::{Body, BodyKind, Message},
+        server::{HasInner, Serve, ServerInner},
+    };
+
+    use super::Cluster;
+
+    /**
+     * remembers broadcast values and relays them to every peer once
+     */
+    #[derive(Debug, Default)]
+    struct Relay {
+        inner: ServerInner,
+        seen: Mutex<Vec<usize>>,
+    }
+
+    impl HasInner for Relay {
+        fn as_inner(&self) -> &ServerInner {
+            &self.inner
+        }
+    }
+
+    #[async_trait]
+    impl Serve for Relay {
+        async fn reply(&self, msg: &Message) -> error::Result<Option<Message>> {
+            match &msg.body.kind {
+                BodyKind::Init { .. } => Ok(self.inner.init(msg)),
+                BodyKind::Broadcast { message } => {
+                    let first_seen = {
+                        let mut seen = self.seen.lock().unwrap();
+                        let first_seen = !seen.contains(message);
+                        if first_seen {
+                            seen.push(*message);
+                        }
+                        first_seen
+                    };
+                    if first_seen {
+                        for peer in self.inner.peers() {
+                            let call = self.inner.rpc(peer, msg.body.kind.clone());
+                            tokio::spawn(call);
+                        }
+                    }
+                    Ok(Some(Message {
+                        src: self.inner.node_id().to_string(),
+                        dst: msg.src.clone(),
+                        body: Body {
+                            kind: BodyKind::BroadcastOk,
+                            msg_id: self.inner.next_id(),
+                            reply_to: Some(msg.body.msg_id),
+                        },
+                    }))
+                }
+                _ => Err(Error::NotSupported(format!("cannot handle msg: {:?}", msg))),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cluster_routes_between_nodes() {
+        let mut cluster: Cluster<Relay> = Cluster::new(3).await;
+        assert_eq!(vec!["n0", "n1", "n2"], cluster.node_ids());
+        assert_eq!(vec!["n0", "n2"], cluster.node("n1").inner.peers());
+
+        let client = cluster.client();
+        let reply_msg = client
+            .request("n0", BodyKind::Broadcast { message: 7 })
+            .await
+            .unwrap();
+        assert_eq!(BodyKind::BroadcastOk, reply_msg.body.kind);
+
+        // init ops, then the broadcast, traffic between nodes is left out
+        let history = cluster.history();
+        assert_eq!(4, history.len());
+        let op = &history.ops()[3];
+        assert_eq!(("c1", "n0"), (op.client.as_str(), op.node.as_str()));
+        assert_eq!(Some(BodyKind::BroadcastOk), op.reply);
+
+        let converged = cluster
+            .wait_until(Duration::from_secs(1), |cluster| {
+                cluster
+                    .nodes()
+                    .all(|(_, node)| node.seen.lock().unwrap().as_slice() == [7])
+            })
+            .await;
+        assert!(converged);
+    }
+
+    #[tokio::test]
+    async fn test_client_error_reply() {
+        let mut cluster: Cluster<Relay> = Cluster::new(1).await;
+        let client = cluster.client();
+
+        let res = client
+            .request(
+                "n0",
+                BodyKind::Read {
+                    key: None,
+                    since: None,
+                },
+            )
+            .await;
+        assert!(matches!(res, Err(Error::NotSupported(_))));
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    error::{self, Error},
+    kv::{LIN_KV, LWW_KV, SEQ_KV},
+    message::{Body, BodyKind, Message},
+};
+
+/**
+ * in-memory stand-in for maelstrom's kv services.
+ * every request is applied atomically in arrival order, which is
+ * linearizable and so good enough for seq-kv and lww-kv as well
+ */
+#[derive(Debug)]
+pub struct KvService {
+    name: String,
+    /// values by the json text of their key
+    values: Mutex<HashMap<String, Value>>,
+    next_id: AtomicUsize,
+}
+
+impl KvService {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            values: Mutex::default(),
+            next_id: AtomicUsize::new(1),
+        }
+    }
+
+    /**
+     * one service for each kv maelstrom provides
+     */
+    pub fn all() -> Vec<Self> {
+        [SEQ_KV, LIN_KV, LWW_KV]
+            .into_iter()
+            .map(Self::new)
+            .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get<K: Serialize>(&self, key: K) -> Option<Value> {
+        let key = serde_json::to_value(key).unwrap().to_string();
+        self.values.lock().unwrap().get(&key).cloned()
+    }
+
+    /**
+     * apply a request and build the reply to it
+     */
+    pub fn handle(&self, msg: &Message) -> Message {
+        let kind = match self.apply(&msg.body.kind) {
+            Ok(kind) => kind,
+            Err(err) => BodyKind::Error {
+                code: err.code(),
+                text: err.text().to_string(),
+            },
+        };
+
+        Message {
+            src: self.name.clone(),
+            dst: msg.src.clone(),
+            body: Body {
+                kind,
+                msg_id: self.next_id.fetch_add(1, Ordering::SeqCst),
+                reply_to: Some(msg.body.msg_id),
+            },
+        }
+    }
+
+    fn apply(&self, kind: &BodyKind) -> error::Result<BodyKind> {
+        let mut values = self.values.lock().unwrap();
+        match kind {
+            BodyKind::Read { key: Some(key), .. } => match values.get(&key.to_string()) {
+                Some(value) => Ok(BodyKind::ReadOk {
+                    messages: None,
+                    version: None,
+                    value: Some(value.clone()),
+                }),
+                None => Err(Error::KeyDoesNotExist(format!(
+                    "key {} does not exist",
+                    key
+                ))),
+            },
+            BodyKind::Write { key, value } => {
+                values.insert(key.to_string(), value.clone());
+                Ok(BodyKind::WriteOk)
+            }
+            BodyKind::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match values.get(&key.to_string()) {
+                Some(value) if value == from => {
+                    values.insert(key.to_string(), to.clone());
+                    Ok(BodyKind::CasOk)
+                }
+                Some(value) => Err(Error::PreconditionFailed(format!(
+                    "expected {}, but key {} holds {}",
+                    from, key, value
+                ))),
+                None if *create_if_not_exists => {
+                    values.insert(key.to_string(), to.clone());
+                    Ok(BodyKind::CasOk)
+                }
+                None => Err(Error::KeyDoesNotExist(format!(
+                    "key {} does not exist",
+                    key
+                ))),
+            },
+            _ => Err(Error::NotSupported(format!(
+                "{} cannot handle {:?}",
+                self.name, kind
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        error::ErrorCode,
+        message::{BodyKind, MessageBuilder},
+    };
+
+    use super::KvService;
+
+    fn call(kv: &KvService, kind: BodyKind) -> BodyKind {
+        let msg = MessageBuilder::new().bodykind(kind).msg_id(3).build();
+        let reply_msg = kv.handle(&msg);
+        assert_eq!(
+            ("seq-kv", "src"),
+            (reply_msg.src.as_str(), reply_msg.dst.as_str())
+        );
+        assert_eq!(Some(3), reply_msg.body.reply_to);
+        reply_msg.body.kind
+    }
+
+    fn error_code(kind: BodyKind) -> ErrorCode {
+        match kind {
+            BodyKind::Error { code, .. } => code,
+            kind => panic!("expected an error, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_handle() {
+        let kv = KvService::new("seq-kv");
+        let read = BodyKind::Read {
+            key: Some(json!("k")),
+            since: None,
+        };
+        assert_eq!(
+            ErrorCode::KeyDoesNotExist,
+            error_code(call(&kv, read.clone()))
+        );
+
+        let write = BodyKind::Write {
+            key: json!("k"),
+            value: json!(1),
+        };
+        assert_eq!(BodyKind::WriteOk, call(&kv, write));
+        assert_eq!(
+            BodyKind::ReadOk {
+                messages: None,
+                version: None,
+                value: Some(json!(1)),
+            },
+            call(&kv, read)
+        );
+
+        let cas = |from, to, create_if_not_exists| BodyKind::Cas {
+            key: json!("k"),
+            from: json!(from),
+            to: json!(to),
+            create_if_not_exists,
+        };
+        assert_eq!(
+            ErrorCode::PreconditionFailed,
+            error_code(call(&kv, cas(0, 2, true)))
+        );
+        assert_eq!(BodyKind::CasOk, call(&kv, cas(1, 2, false)));
+        assert_eq!(Some(json!(2)), kv.get("k"));
+
+        let msg = BodyKind::Generate;
+        assert_eq!(ErrorCode::NotSupported, error_code(call(&kv, msg)));
+    }
+
+    #[test]
+    fn test_cas_serde() {
+        let kind = BodyKind::Cas {
+            key: json!(1),
+            from: json!(2),
+            to: json!(3),
+            create_if_not_exists: false,
+        };
+        let line = serde_json::to_string(&kind).unwrap();
+        assert_eq!(r#"{"type":"cas","key":1,"from":2,"to":3}"#, line);
+
+        let line = r#"{"type":"cas","key":1,"from":2,"to":3,"create_if_not_exists":true}"#;
+        let kind: BodyKind = serde_json::from_str(line).unwrap();
+        assert!(matches!(
+            kind,
+            BodyKind::Cas {
+                create_if_not_exists: true,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,7 @@
+pub mod cluster;
+pub mod kv;
+pub mod simulation;
+
+pub use cluster::{Client, Cluster};
+pub use kv::KvService;
+pub use simulation::{SimConfig, Simulation};
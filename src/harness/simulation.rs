@@ -0,0 +1,720 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap},
+    ops::RangeInclusive,
+    sync::Arc,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::sync::mpsc;
+
+use super::KvService;
+use crate::{
+    checker::History,
+    message::{Body, BodyKind, Message},
+    server::Serve,
+    stats::StatsSnapshot,
+    timer::{Task, TaskFn, Timer},
+};
+
+/// how long `call` waits for a reply, in virtual milliseconds
+const CALL_TIMEOUT_MS: u64 = 10_000;
+
+/**
+ * seed and faults of a simulation.
+ * faults only hit messages between nodes, clients and kv services always reach the cluster
+ */
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub seed: u64,
+    /// delivery delay of every message, in virtual milliseconds
+    pub latency: RangeInclusive<u64>,
+    pub drop_rate: f64,
+    pub duplicate_rate: f64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            latency: 1..=10,
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+        }
+    }
+}
+
+impl SimConfig {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+
+    pub fn latency(mut self, min_ms: u64, max_ms: u64) -> Self {
+        self.latency = min_ms..=max_ms;
+        self
+    }
+
+    pub fn drop_rate(mut self, drop_rate: f64) -> Self {
+        self.drop_rate = drop_rate;
+        self
+    }
+
+    pub fn duplicate_rate(mut self, duplicate_rate: f64) -> Self {
+        self.duplicate_rate = duplicate_rate;
+        self
+    }
+}
+
+#[derive(Debug)]
+enum Event {
+    Deliver(Message),
+    Timer(usize),
+}
+
+enum SimTimer {
+    Every {
+        node_id: String,
+        period: u64,
+        task: TaskFn,
+    },
+    After {
+        node_id: String,
+        task: Option<Task>,
+    },
+}
+
+/**
+ * nodes run one event at a time on a virtual clock.
+ * every delivery, drop, duplicate and timer is drawn from a seeded rng,
+ * so the same seed replays the exact same schedule.
+ *
+ * handlers run inline and their messages are collected right after.
+ * work a handler spawns on tokio, like waiting for an rpc reply, still
+ * runs in real time and is outside of the replayed schedule
+ */
+pub struct Simulation<S: Serve> {
+    config: SimConfig,
+    rng: StdRng,
+    now: u64,
+    seq: u64,
+    nodes: BTreeMap<String, Arc<S>>,
+    services: BTreeMap<String, KvService>,
+    outboxes: BTreeMap<String, mpsc::UnboundedReceiver<Message>>,
+    /// partition group of every node, nodes of different groups can't talk
+    groups: HashMap<String, usize>,
+    queue: BinaryHeap<Reverse<(u64, u64, usize)>>,
+    events: HashMap<usize, Event>,
+    next_event: usize,
+    timers: Vec<SimTimer>,
+    next_client_msg_id: usize,
+    replies: Vec<Message>,
+    history: History,
+    trace: Vec<String>,
+}
+
+impl<S: Serve + Default> Simulation<S> {
+    /**
+     * start `count` default nodes named n0, n1, ...
+     */
+    pub async fn new(count: usize, config: SimConfig) -> Self {
+        Self::with_nodes((0..count).map(|_| S::default()).collect(), config).await
+    }
+}
+
+impl<S: Serve> Simulation<S> {
+    pub async fn with_nodes(nodes: Vec<S>, config: SimConfig) -> Self {
+        let nodes: BTreeMap<String, Arc<S>> = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(i, node)| (format!("n{}", i), Arc::new(node)))
+            .collect();
+        let outboxes = nodes
+            .iter()
+            .map(|(id, node)| {
+                node.as_inner().timers().set_manual();
+                let outbox = node
+                    .as_inner()
+                    .take_outbox()
+                    .expect("outbox is taken, a node can only join one simulation");
+                (id.clone(), outbox)
+            })
+            .collect();
+
+        let mut sim = Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            now: 0,
+            seq: 0,
+            nodes,
+            services: KvService::all()
+                .into_iter()
+                .map(|service| (service.name().to_string(), service))
+                .collect(),
+            outboxes,
+            groups: HashMap::new(),
+            queue: BinaryHeap::new(),
+            events: HashMap::new(),
+            next_event: 0,
+            timers: Vec::new(),
+            next_client_msg_id: 0,
+            replies: Vec::new(),
+            history: History::default(),
+            trace: Vec::new(),
+        };
+        sim.init().await;
+        sim
+    }
+
+    async fn init(&mut self) {
+        let node_ids = self.node_ids();
+        let mut msg_ids = Vec::with_capacity(node_ids.len());
+        for node_id in node_ids.iter() {
+            let kind = BodyKind::Init {
+                node_id: node_id.clone(),
+                node_ids: node_ids.clone(),
+            };
+            msg_ids.push(self.request("c0", node_id, kind));
+        }
+
+        for msg_id in msg_ids {
+            let reply_msg = self
+                .wait_reply("c0", msg_id)
+                .await
+                .unwrap_or_else(|| panic!("node didn't reply to init {}", msg_id));
+            assert_eq!(BodyKind::InitOk, reply_msg.body.kind);
+        }
+    }
+
+    /// virtual milliseconds since the simulation started
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn config_mut(&mut self) -> &mut SimConfig {
+        &mut self.config
+    }
+
+    pub fn node_ids(&self) -> Vec<String> {
+        self.nodes.keys().cloned().collect()
+    }
+
+    pub fn node(&self, node_id: &str) -> &S {
+        &self.nodes[node_id]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (&String, &S)> {
+        self.nodes.iter().map(|(id, node)| (id, node.as_ref()))
+    }
+
+    /**
+     * the kv service named e.g. `seq-kv`
+     */
+    pub fn kv(&self, name: &str) -> &KvService {
+        &self.services[name]
+    }
+
+    /**
+     * one line per scheduling decision, equal for runs with the same seed
+     */
+    pub fn trace(&self) -> &[String] {
+        &self.trace
+    }
+
+    /**
+     * every reply received by clients, in delivery order
+     */
+    pub fn replies(&self) -> &[Message] {
+        &self.replies
+    }
+
+    /**
+     * counters of all nodes added up
+     */
+    pub fn stats(&self) -> StatsSnapshot {
+        self.nodes
+            .values()
+            .map(|node| node.as_inner().stats().snapshot())
+            .fold(StatsSnapshot::default(), |sum, stats| sum + stats)
+    }
+
+    /**
+     * every client request and the replies delivered for them
+     */
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /**
+     * split the cluster, nodes of different groups can't reach each other.
+     * nodes missing from groups are isolated
+     */
+    pub fn partition(&mut self, groups: &[&[&str]]) {
+        self.groups.clear();
+        for (i, group) in groups.iter().enumerate() {
+            for node_id in group.iter() {
+                self.groups.insert(node_id.to_string(), i);
+            }
+        }
+        let isolated: Vec<String> = self
+            .nodes
+            .keys()
+            .filter(|id| !self.groups.contains_key(*id))
+            .cloned()
+            .collect();
+        for (i, node_id) in isolated.into_iter().enumerate() {
+            self.groups.insert(node_id, groups.len() + i);
+        }
+        self.trace
+            .push(format!("{} partition {:?}", self.now, groups));
+    }
+
+    pub fn heal(&mut self) {
+        self.groups.clear();
+        self.trace.push(format!("{} heal", self.now));
+    }
+
+    fn can_reach(&self, src: &str, dst: &str) -> bool {
+        match (self.groups.get(src), self.groups.get(dst)) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+    }
+
+    /**
+     * queue a request from client to node, return its msg_id
+     */
+    pub fn request(&mut self, client: &str, node_id: &str, kind: BodyKind) -> usize {
+        self.next_client_msg_id += 1;
+        let msg_id = self.next_client_msg_id;
+        let msg = Message {
+            src: client.to_string(),
+            dst: node_id.to_string(),
+            body: Body {
+                kind,
+                msg_id,
+                reply_to: None,
+            },
+        };
+        self.history.record(&msg);
+        self.transmit(msg);
+        msg_id
+    }
+
+    /**
+     * send kind to node as client c1 and run until its reply arrives
+     */
+    pub async fn call(&mut self, node_id: &str, kind: BodyKind) -> Option<Message> {
+        let msg_id = self.request("c1", node_id, kind);
+        self.wait_reply("c1", msg_id).await
+    }
+
+    async fn wait_reply(&mut self, client: &str, msg_id: usize) -> Option<Message> {
+        let deadline = self.now + CALL_TIMEOUT_MS;
+        let find = |replies: &[Message]| {
+            replies
+                .iter()
+                .find(|msg| msg.dst == client && msg.body.reply_to == Some(msg_id))
+                .cloned()
+        };
+
+        loop {
+            if let Some(reply_msg) = find(&self.replies) {
+                return Some(reply_msg);
+            }
+            match self.queue.peek() {
+                Some(Reverse((at, _, _))) if *at <= deadline => self.step().await,
+                _ => return None,
+            };
+        }
+    }
+
+    /**
+     * process every event up to `ms` virtual milliseconds from now
+     */
+    pub async fn run_for(&mut self, ms: u64) {
+        let deadline = self.now + ms;
+        while let Some(Reverse((at, _, _))) = self.queue.peek() {
+            if *at > deadline {
+                break;
+            }
+            self.step().await;
+        }
+        self.now = deadline;
+    }
+
+    /**
+     * process events until `cond` holds or `ms` virtual milliseconds pass,
+     * return whether it held.
+     * for effects of work handlers spawn, which runs in real time and may
+     * take any number of events to land
+     */
+    pub async fn run_until<F>(&mut self, ms: u64, cond: F) -> bool
+    where
+        F: Fn(&Self) -> bool,
+    {
+        let deadline = self.now + ms;
+        loop {
+            if cond(self) {
+                return true;
+            }
+            match self.queue.peek() {
+                Some(Reverse((at, _, _))) if *at <= deadline => self.step().await,
+                _ => {
+                    self.now = deadline;
+                    return cond(self);
+                }
+            };
+        }
+    }
+
+    /**
+     * process the next event, return false if there is none
+     */
+    pub async fn step(&mut self) -> bool {
+        let Some(Reverse((at, _, event_id))) = self.queue.pop() else {
+            return false;
+        };
+        self.now = at;
+        let event = self.events.remove(&event_id).unwrap();
+
+        match event {
+            Event::Deliver(msg) => self.deliver(msg).await,
+            Event::Timer(timer_id) => self.fire(timer_id).await,
+        }
+
+        // let tasks spawned by handlers make progress before collecting output
+        tokio::task::yield_now().await;
+        self.collect();
+        true
+    }
+
+    async fn deliver(&mut self, msg: Message) {
+        if !self.can_reach(&msg.src, &msg.dst) {
+            self.trace.push(format!(
+                "{} partitioned {} -> {} {}",
+                self.now,
+                msg.src,
+                msg.dst,
+                canonical(&msg)
+            ));
+            return;
+        }
+        self.trace.push(format!(
+            "{} deliver {} -> {} {}",
+            self.now,
+            msg.src,
+            msg.dst,
+            canonical(&msg)
+        ));
+
+        if let Some(node) = self.nodes.get(&msg.dst) {
+            node.as_inner().stats().record_received(&msg);
+            if !node.as_inner().resolve(&msg) {
+                node.clone().process(msg).await;
+            }
+        } else if let Some(service) = self.services.get(&msg.dst) {
+            let reply_msg = service.handle(&msg);
+            self.transmit(reply_msg);
+        } else {
+            self.history.record(&msg);
+            self.replies.push(msg);
+        }
+    }
+
+    async fn fire(&mut self, timer_id: usize) {
+        let task = match &mut self.timers[timer_id] {
+            SimTimer::Every {
+                node_id,
+                period,
+                task,
+            } => {
+                self.trace
+                    .push(format!("{} timer {} every {}", self.now, node_id, period));
+                let period = *period;
+                let fut = task();
+                self.schedule(self.now + period, Event::Timer(timer_id));
+                fut
+            }
+            SimTimer::After { node_id, task } => {
+                self.trace
+                    .push(format!("{} timer {} after", self.now, node_id));
+                match task.take() {
+                    Some(task) => task,
+                    None => return,
+                }
+            }
+        };
+        task.await;
+    }
+
+    /**
+     * pick up messages and timers the nodes produced since the last step
+     */
+    fn collect(&mut self) {
+        let mut sent = Vec::new();
+        for (node_id, outbox) in self.outboxes.iter_mut() {
+            let stats = self.nodes[node_id].as_inner().stats();
+            while let Ok(msg) = outbox.try_recv() {
+                stats.record_sent(&msg);
+                sent.push(msg);
+            }
+        }
+        // a node may emit in hash order, sort so the schedule doesn't depend on it
+        sent.sort_by_cached_key(|msg| (msg.src.clone(), msg.dst.clone(), canonical(msg)));
+        for msg in sent {
+            self.transmit(msg);
+        }
+
+        let mut registered = Vec::new();
+        for (node_id, node) in self.nodes.iter() {
+            for timer in node.as_inner().timers().take_pending() {
+                registered.push((node_id.clone(), timer));
+            }
+        }
+        for (node_id, timer) in registered {
+            let timer_id = self.timers.len();
+            let at = match timer {
+                Timer::Every { period, task } => {
+                    let period = period.as_millis() as u64;
+                    self.timers.push(SimTimer::Every {
+                        node_id,
+                        period,
+                        task,
+                    });
+                    self.now + period
+                }
+                Timer::After { delay, task } => {
+                    self.timers.push(SimTimer::After {
+                        node_id,
+                        task: Some(task),
+                    });
+                    self.now + delay.as_millis() as u64
+                }
+            };
+            self.schedule(at, Event::Timer(timer_id));
+        }
+    }
+
+    /**
+     * put msg on the wire, deciding its latency and faults
+     */
+    fn transmit(&mut self, msg: Message) {
+        // always draw the same numbers so one decision doesn't shift the next ones
+        let dropped = self.rng.gen_bool(self.config.drop_rate);
+        let duplicated = self.rng.gen_bool(self.config.duplicate_rate);
+        let latency = self.rng.gen_range(self.config.latency.clone());
+        let dup_latency = self.rng.gen_range(self.config.latency.clone());
+
+        let between_nodes = self.nodes.contains_key(&msg.src) && self.nodes.contains_key(&msg.dst);
+        if between_nodes && dropped {
+            self.trace.push(format!(
+                "{} drop {} -> {} {}",
+                self.now,
+                msg.src,
+                msg.dst,
+                canonical(&msg)
+            ));
+            return;
+        }
+
+        if between_nodes && duplicated {
+            self.trace.push(format!(
+                "{} duplicate {} -> {} {}",
+                self.now,
+                msg.src,
+                msg.dst,
+                canonical(&msg)
+            ));
+            self.schedule(self.now + dup_latency, Event::Deliver(msg.clone()));
+        }
+        self.schedule(self.now + latency, Event::Deliver(msg));
+    }
+
+    fn schedule(&mut self, at: u64, event: Event) {
+        let event_id = self.next_event;
+        self.next_event += 1;
+        self.events.insert(event_id, event);
+        self.seq += 1;
+        self.queue.push(Reverse((at, self.seq, event_id)));
+    }
+}
+
+/**
+ * msg body with sorted keys and without ids, stable across runs
+ */
+fn canonical(msg: &Message) -> String {
+    serde_json::to_value(&msg.body.kind).unwrap().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeSet,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use crate::{
+        error::{self, Error},
+        message::{Body, BodyKind, Message},
+        server::{HasInner, Serve, ServerInner},
+    };
+
+    use super::{SimConfig, Simulation};
+
+    /**
+     * keeps broadcast values and pushes all of them to every peer each 100ms
+     */
+    #[derive(Debug, Default)]
+    struct Gossip {
+        inner: ServerInner,
+        values: Mutex<BTreeSet<usize>>,
+    }
+
+    impl HasInner for Gossip {
+        fn as_inner(&self) -> &ServerInner {
+            &self.inner
+        }
+    }
+
+    #[async_trait]
+    impl Serve for Gossip {
+        async fn reply(&self, msg: &Message) -> error::Result<Option<Message>> {
+            let kind = match &msg.body.kind {
+                BodyKind::Init { .. } => return Ok(self.inner.init(msg)),
+                BodyKind::Broadcast { message } => {
+                    self.values.lock().unwrap().insert(*message);
+                    BodyKind::BroadcastOk
+                }
+                BodyKind::Gossip { messages } => {
+                    self.values.lock().unwrap().extend(messages);
+                    return Ok(None);
+                }
+                _ => return Err(Error::NotSupported(format!("cannot handle msg: {:?}", msg))),
+            };
+            Ok(Some(Message {
+                src: self.inner.node_id().to_string(),
+                dst: msg.src.clone(),
+                body: Body {
+                    kind,
+                    msg_id: self.inner.next_id(),
+                    reply_to: Some(msg.body.msg_id),
+                },
+            }))
+        }
+
+        fn schedule(self: Arc<Self>) {
+            let node = self.clone();
+            self.inner
+                .timers()
+                .every(Duration::from_millis(100), move || {
+                    let node = node.clone();
+                    async move {
+                        let messages: Vec<usize> =
+                            node.values.lock().unwrap().iter().copied().collect();
+                        for peer in node.inner.peers() {
+                            node.inner.send(Message {
+                                src: node.inner.node_id().to_string(),
+                                dst: peer.to_string(),
+                                body: Body {
+                                    kind: BodyKind::Gossip {
+                                        messages: messages.clone(),
+                                    },
+                                    msg_id: node.inner.next_id(),
+                                    reply_to: None,
+                                },
+                            });
+                        }
+                    }
+                });
+        }
+    }
+
+    fn values(sim: &Simulation<Gossip>, node_id: &str) -> Vec<usize> {
+        sim.node(node_id)
+            .values
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    async fn run(seed: u64) -> Simulation<Gossip> {
+        let config = SimConfig::new(seed)
+            .latency(1, 80)
+            .drop_rate(0.2)
+            .duplicate_rate(0.1);
+        let mut sim: Simulation<Gossip> = Simulation::new(4, config).await;
+        for i in 0..10 {
+            sim.request(
+                "c1",
+                &format!("n{}", i % 4),
+                BodyKind::Broadcast { message: i },
+            );
+            sim.run_for(30).await;
+        }
+        sim.run_for(1_000).await;
+        sim
+    }
+
+    #[tokio::test]
+    async fn test_same_seed_same_schedule() {
+        let first = run(42).await;
+        let second = run(42).await;
+        assert_eq!(first.trace(), second.trace());
+        assert!(first.trace().iter().any(|line| line.contains(" drop ")));
+        assert!(first
+            .trace()
+            .iter()
+            .any(|line| line.contains(" duplicate ")));
+
+        let other = run(7).await;
+        assert_ne!(first.trace(), other.trace());
+
+        // gossip keeps retrying, so drops don't lose values
+        for node_id in first.node_ids() {
+            assert_eq!((0..10).collect::<Vec<_>>(), values(&first, &node_id));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_partition_and_heal() {
+        let mut sim: Simulation<Gossip> = Simulation::new(3, SimConfig::new(1)).await;
+        sim.partition(&[&["n0", "n1"]]);
+
+        let reply_msg = sim.call("n0", BodyKind::Broadcast { message: 5 }).await;
+        assert_eq!(BodyKind::BroadcastOk, reply_msg.unwrap().body.kind);
+        let reply_msg = sim.call("n2", BodyKind::Broadcast { message: 6 }).await;
+        assert_eq!(BodyKind::BroadcastOk, reply_msg.unwrap().body.kind);
+        sim.run_for(500).await;
+
+        assert_eq!(vec![5], values(&sim, "n1"));
+        assert_eq!(vec![6], values(&sim, "n2"));
+
+        sim.heal();
+        sim.run_for(500).await;
+        for node_id in sim.node_ids() {
+            assert_eq!(vec![5, 6], values(&sim, &node_id));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drop_everything_between_nodes() {
+        let mut sim: Simulation<Gossip> =
+            Simulation::new(2, SimConfig::new(3).drop_rate(1.0)).await;
+        assert!(sim
+            .call("n0", BodyKind::Broadcast { message: 1 })
+            .await
+            .is_some());
+        sim.run_for(1_000).await;
+
+        assert!(values(&sim, "n1").is_empty());
+        assert!(sim.now() >= 1_000);
+    }
+}
@@ -1,80 +1,96 @@
 use crate::{
-    message::{Body, BodyKind, Message, Payload},
+    error::{self, Error},
+    message::{Body, BodyKind, Message},
     server::{HasInner, Serve, ServerInner},
 };
 use async_trait::async_trait;
-use core::panic;
-use serde_json::json;
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::RwLock;
 
-use super::storage::Storage;
+use super::{
+    log_store::{self, LogStore},
+    segment::FsyncPolicy,
+    storage::{Backend, StorageConfig},
+};
 
 #[derive(Debug, Default)]
 pub struct KafkaServer {
     inner: ServerInner,
-    storage: Option<Storage>,
-    commit_offsets: HashMap<String, usize>,
+    config: StorageConfig,
+    /// opened on init, polls share the read lock while sends append under the write lock
+    storage: RwLock<Option<Box<dyn LogStore>>>,
+    commit_offsets: Mutex<HashMap<String, usize>>,
+}
+
+fn storage_not_ready() -> Error {
+    Error::TemporarilyUnavailable("storage is not ready".to_string())
 }
 
 impl KafkaServer {
+    pub fn new(config: StorageConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
     /**
      * This message requests that a "msg" value be appended to a log identified by "key"
      */
-    async fn send(&mut self, msg: &Message) -> Message {
-        let key = msg.body.payload.get_str("key");
-        let content = msg.body.payload.get_usize("msg");
-
-        let offset = self.storage_mut().append(key, content).await;
+    async fn send(&self, msg: &Message, key: &str, content: usize) -> error::Result<Message> {
+        let offset = self
+            .storage
+            .write()
+            .await
+            .as_mut()
+            .ok_or_else(storage_not_ready)?
+            .append(key, content)
+            .await;
 
         let body = Body {
-            kind: BodyKind::SendOk,
-            msg_id: self.inner.next_msg_id(),
+            kind: BodyKind::SendOk { offset },
+            msg_id: self.inner.next_id(),
             reply_to: Some(msg.body.msg_id),
-            payload: Payload::init("offset", json!(offset)),
         };
 
-        Message {
+        Ok(Message {
             src: self.inner.node_id().to_string(),
             dst: msg.src.to_string(),
             body,
-        }
-    }
-
-    fn storage_mut(&mut self) -> &mut Storage {
-        match &mut self.storage {
-            Some(ref mut s) => s,
-            None => panic!("storage error"),
-        }
-    }
-
-    fn storage(&self) -> &Storage {
-        self.storage.as_ref().unwrap()
+        })
     }
 
     /**
      * This message requests that a node return messages from a set of logs
      * starting from the given offset in each log
      */
-    pub async fn poll(&mut self, msg: &Message) -> Message {
-        let offsets = msg.body.payload.get_raw("offsets").clone();
-        let offsets: HashMap<String, usize> = serde_json::from_value(offsets).unwrap();
-
-        let msgs = self.storage().read_from(&offsets).await;
+    pub async fn poll(
+        &self,
+        msg: &Message,
+        offsets: &HashMap<String, usize>,
+    ) -> error::Result<Message> {
+        let msgs = self
+            .storage
+            .read()
+            .await
+            .as_ref()
+            .ok_or_else(storage_not_ready)?
+            .read_from(offsets)
+            .await;
         let body = Body {
-            kind: BodyKind::PollOk,
-            msg_id: self.inner.next_msg_id(),
+            kind: BodyKind::PollOk { msgs },
+            msg_id: self.inner.next_id(),
             reply_to: Some(msg.body.msg_id),
-            payload: Payload::init(
-                "msgs",
-                serde_json::to_value(msgs).expect("failed to serialize msgs in poll"),
-            ),
         };
 
-        Message {
+        Ok(Message {
             src: self.inner.node_id().to_string(),
             dst: msg.src.to_string(),
             body,
-        }
+        })
     }
 
     /**
@@ -89,26 +105,28 @@ impl KafkaServer {
      *      }
      * }
      */
-    pub async fn commit_offsets(&mut self, msg: &Message) -> Message {
-        let offsets = msg.body.payload.get_raw("offsets").clone();
-        let offsets: HashMap<String, usize> = serde_json::from_value(offsets).unwrap();
-        self.merge_offsets(&offsets);
+    pub async fn commit_offsets(&self, msg: &Message, offsets: &HashMap<String, usize>) -> Message {
+        self.merge_offsets(offsets);
 
         Message {
             src: self.inner.node_id().to_string(),
             dst: msg.src.to_string(),
             body: Body {
                 kind: BodyKind::CommitOffsetsOk,
-                msg_id: self.inner.next_msg_id(),
+                msg_id: self.inner.next_id(),
                 reply_to: Some(msg.body.msg_id),
-                payload: Payload::default(),
             },
         }
     }
 
-    fn merge_offsets(&mut self, offsets: &HashMap<String, usize>) {
+    /**
+     * committed offsets only move forward, a commit behind the current one is ignored
+     */
+    fn merge_offsets(&self, offsets: &HashMap<String, usize>) {
+        let mut commit_offsets = self.commit_offsets.lock().unwrap();
         for (key, offset) in offsets.iter() {
-            self.commit_offsets.insert(key.to_string(), *offset);
+            let committed = commit_offsets.entry(key.to_string()).or_default();
+            *committed = (*committed).max(*offset);
         }
     }
 
@@ -116,25 +134,25 @@ impl KafkaServer {
      * This message returns a map of committed offsets for a given set of logs.
      * Clients use this to figure out where to start consuming from in a given log.
      */
-    pub async fn list_committed_offsets(&mut self, msg: &Message) -> Message {
-        let keys = msg.body.payload.get_raw("keys").clone();
-        let keys: Vec<String> = serde_json::from_value(keys).unwrap();
-        let keys: HashSet<String> = HashSet::from_iter(keys);
+    pub async fn list_committed_offsets(&self, msg: &Message, keys: &[String]) -> Message {
+        let keys: HashSet<&String> = HashSet::from_iter(keys);
 
-        let filtered: HashMap<&String, &usize> = self
+        let offsets: HashMap<String, usize> = self
             .commit_offsets
+            .lock()
+            .unwrap()
             .iter()
             .filter(|&(k, _)| keys.contains(k))
+            .map(|(k, v)| (k.to_string(), *v))
             .collect();
 
         Message {
             src: self.inner.node_id().to_string(),
             dst: msg.src.to_string(),
             body: Body {
-                kind: BodyKind::ListCommittedOffsetsOk,
-                msg_id: self.inner.next_msg_id(),
+                kind: BodyKind::ListCommittedOffsetsOk { offsets },
+                msg_id: self.inner.next_id(),
                 reply_to: Some(msg.body.msg_id),
-                payload: Payload::init("offsets", json!(filtered)),
             },
         }
     }
@@ -142,187 +160,282 @@ impl KafkaServer {
 
 #[async_trait]
 impl Serve for KafkaServer {
-    async fn reply(&mut self, msg: &Message) -> Option<Message> {
-        match msg.body.kind {
-            BodyKind::Init => {
+    async fn reply(&self, msg: &Message) -> error::Result<Option<Message>> {
+        match &msg.body.kind {
+            BodyKind::Init { .. } => {
                 let reply_msg = self.inner.init(msg);
-                self.storage = Some(Storage::new().await);
-                reply_msg
+                *self.storage.write().await =
+                    Some(log_store::open(self.inner.node_id(), &self.config).await);
+                Ok(reply_msg)
+            }
+            BodyKind::Send { key, msg: content } => Ok(Some(self.send(msg, key, *content).await?)),
+            BodyKind::Poll { offsets } => Ok(Some(self.poll(msg, offsets).await?)),
+            BodyKind::CommitOffsets { offsets } => {
+                Ok(Some(self.commit_offsets(msg, offsets).await))
             }
-            BodyKind::Send => Some(self.send(msg).await),
-            BodyKind::Poll => Some(self.poll(msg).await),
-            BodyKind::CommitOffsets => Some(self.commit_offsets(msg).await),
-            BodyKind::ListCommittedOffsets => Some(self.list_committed_offsets(msg).await),
-            _ => panic!("{}", format!("cannot handle msg: {:?}", msg)),
+            BodyKind::ListCommittedOffsets { keys } => {
+                Ok(Some(self.list_committed_offsets(msg, keys).await))
+            }
+            _ => Err(Error::NotSupported(format!("cannot handle msg: {:?}", msg))),
+        }
+    }
+
+    /**
+     * with an interval fsync policy, sync what was appended since the last sync
+     * every interval, so the last records of a burst don't wait for the next append
+     */
+    fn schedule(self: Arc<Self>) {
+        let FsyncPolicy::Interval(interval) = self.config.segment.fsync else {
+            return;
+        };
+        if self.config.backend != Backend::Disk {
+            return;
         }
+        let server = self.clone();
+        self.inner.timers().every(interval, move || {
+            let server = server.clone();
+            async move {
+                if let Some(storage) = server.storage.write().await.as_mut() {
+                    if storage.unsynced() > 0 {
+                        storage.sync().await;
+                    }
+                }
+            }
+        });
     }
 }
 
 impl HasInner for KafkaServer {
-    fn as_inner(&mut self) -> &mut ServerInner {
-        &mut self.inner
+    fn as_inner(&self) -> &ServerInner {
+        &self.inner
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, fs};
+    use std::{collections::HashMap, fs, time::Duration};
 
     use crate::{
-        kafka::storage::tests::clean_disk_data,
+        checker::kafka,
+        harness::{SimConfig, Simulation},
+        kafka::{
+            record::{self, Record},
+            segment::FsyncPolicy,
+            storage::StorageConfig,
+        },
         message::{BodyKind, MessageBuilder},
         server::Serve,
-        utils::tests::generate_random_node_id,
+        utils::tests::{generate_random_node_id, TempDir},
     };
-    use serde_json::json;
 
     use crate::kafka::KafkaServer;
 
     #[tokio::test]
     async fn test_send() {
         let node_id = generate_random_node_id();
-        let mut server = KafkaServer::default();
+        let dir = TempDir::new();
+        let server = KafkaServer::new(StorageConfig::new(dir.path()));
         let msg = MessageBuilder::new()
-            .insert("node_id", json!(&node_id))
+            .bodykind(BodyKind::Init {
+                node_id,
+                node_ids: vec![],
+            })
             .build();
-        server.reply(&msg).await;
+        server.reply(&msg).await.unwrap();
 
         let msg = MessageBuilder::new()
-            .bodykind(BodyKind::Send)
+            .bodykind(BodyKind::Send {
+                key: "k1".to_string(),
+                msg: 123,
+            })
             .msg_id(200)
-            .insert("key", json!("k1"))
-            .insert("msg", json!(123))
             .build();
-        let reply_msg = server.reply(&msg).await.unwrap();
-
-        assert_eq!(BodyKind::SendOk, reply_msg.body.kind);
-        assert_eq!(0, server.storage().offsets()["k1"]);
-        let buffer = fs::read_to_string(format!("log/{}", server.storage().log_name())).unwrap();
-        let buffer = buffer.trim_end();
-        assert_eq!("0:k1:123", buffer);
-
-        drop(server);
-        clean_disk_data().await;
+        let reply_msg = server.reply(&msg).await.unwrap().unwrap();
+
+        assert_eq!(BodyKind::SendOk { offset: 0 }, reply_msg.body.kind);
+        {
+            let storage = server.storage.read().await;
+            let storage = storage.as_ref().unwrap();
+            assert_eq!(1, storage.high_watermark("k1"));
+            let segment = dir
+                .path()
+                .join(server.inner.node_id())
+                .join("kafka")
+                .join("6b31")
+                .join(format!("{:020}.log", 0));
+            let buffer = fs::read(segment).unwrap();
+            assert_eq!(Ok(()), record::check_header(&buffer));
+            let (records, _) = Record::decode_all(&buffer[record::HEADER_BYTES as usize..]);
+            assert_eq!(
+                vec![Record {
+                    offset: 0,
+                    msg: 123
+                }],
+                records
+            );
+        }
     }
 
     #[tokio::test]
     async fn test_poll() {
         let node_id = generate_random_node_id();
-        let mut server = KafkaServer::default();
+        let dir = TempDir::new();
+        let server = KafkaServer::new(StorageConfig::new(dir.path()));
         let msg = MessageBuilder::new()
-            .insert("node_id", json!(&node_id))
+            .bodykind(BodyKind::Init {
+                node_id,
+                node_ids: vec![],
+            })
             .build();
-        server.reply(&msg).await;
-
-        let builder = MessageBuilder::new().bodykind(BodyKind::Send);
+        server.reply(&msg).await.unwrap();
+
+        let send = |key: &str, msg: usize| {
+            MessageBuilder::new()
+                .bodykind(BodyKind::Send {
+                    key: key.to_string(),
+                    msg,
+                })
+                .build()
+        };
 
-        let msg = builder
-            .clone()
-            .insert("key", json!("k1"))
-            .insert("msg", json!(123))
-            .build();
-        server.reply(&msg).await;
-        let msg = builder
-            .clone()
-            .insert("key", json!("k1"))
-            .insert("msg", json!(123))
-            .build();
-        server.reply(&msg).await;
-        let msg = builder
-            .clone()
-            .insert("key", json!("k2"))
-            .insert("msg", json!(122))
-            .build();
-        server.reply(&msg).await;
+        server.reply(&send("k1", 123)).await.unwrap();
+        server.reply(&send("k1", 123)).await.unwrap();
+        server.reply(&send("k2", 122)).await.unwrap();
 
         let msg = MessageBuilder::new()
-            .bodykind(BodyKind::Poll)
-            .insert(
-                "offsets",
-                serde_json::to_value(HashMap::from([
-                    ("k1".to_string(), 1),
-                    ("k2".to_string(), 0),
-                ]))
-                .unwrap(),
-            )
+            .bodykind(BodyKind::Poll {
+                offsets: HashMap::from([("k1".to_string(), 1), ("k2".to_string(), 0)]),
+            })
             .build();
-        let reply_msg = server.reply(&msg).await.unwrap();
-        let msgs = reply_msg.body.payload.get_raw("msgs");
-        let msgs: HashMap<String, Vec<[usize; 2]>> = serde_json::from_value(msgs.clone()).unwrap();
+        let reply_msg = server.reply(&msg).await.unwrap().unwrap();
+        let BodyKind::PollOk { msgs } = reply_msg.body.kind else {
+            panic!("expect poll_ok, got {:?}", reply_msg.body.kind);
+        };
         assert_eq!([1_usize, 123_usize], msgs["k1"][0]);
         assert_eq!([0_usize, 122_usize], msgs["k2"][0]);
-
-        drop(server);
-        clean_disk_data().await;
     }
 
     #[tokio::test]
     async fn test_commit_offsets() {
         let node_id = generate_random_node_id();
-        let mut server = KafkaServer::default();
+        let dir = TempDir::new();
+        let server = KafkaServer::new(StorageConfig::new(dir.path()));
         let msg = MessageBuilder::new()
-            .insert("node_id", json!(&node_id))
+            .bodykind(BodyKind::Init {
+                node_id,
+                node_ids: vec![],
+            })
             .build();
-        server.reply(&msg).await;
+        server.reply(&msg).await.unwrap();
 
-        let builder = MessageBuilder::new().bodykind(BodyKind::CommitOffsets);
-
-        let msg = builder
-            .clone()
-            .insert(
-                "offsets",
-                json!(HashMap::from([("k1", 1000), ("k2", 2000)])),
-            )
+        let msg = MessageBuilder::new()
+            .bodykind(BodyKind::CommitOffsets {
+                offsets: HashMap::from([("k1".to_string(), 1000), ("k2".to_string(), 2000)]),
+            })
             .build();
-        let reply_msg = server.reply(&msg).await.unwrap();
+        let reply_msg = server.reply(&msg).await.unwrap().unwrap();
         assert_eq!(BodyKind::CommitOffsetsOk, reply_msg.body.kind);
-
-        drop(server);
-        clean_disk_data().await;
     }
 
     #[tokio::test]
     async fn test_list_committed_offsets() {
         let node_id = generate_random_node_id();
-        let mut server = KafkaServer::default();
+        let dir = TempDir::new();
+        let server = KafkaServer::new(StorageConfig::new(dir.path()));
         let msg = MessageBuilder::new()
-            .insert("node_id", json!(&node_id))
+            .bodykind(BodyKind::Init {
+                node_id,
+                node_ids: vec![],
+            })
             .build();
-        server.reply(&msg).await;
-
-        let builder = MessageBuilder::new().bodykind(BodyKind::CommitOffsets);
-        let msg = builder
-            .clone()
-            .insert(
-                "offsets",
-                json!(HashMap::from([("k1", 1000), ("k2", 2000)])),
-            )
-            .build();
-        server.reply(&msg).await;
-
-        let msg = builder
-            .clone()
-            .insert(
-                "offsets",
-                json!(HashMap::from([("k3", 2000), ("k2", 2500)])),
-            )
-            .build();
-        server.reply(&msg).await;
+        server.reply(&msg).await.unwrap();
+
+        let commit = |offsets: [(&str, usize); 2]| {
+            MessageBuilder::new()
+                .bodykind(BodyKind::CommitOffsets {
+                    offsets: offsets.map(|(k, v)| (k.to_string(), v)).into(),
+                })
+                .build()
+        };
+        server
+            .reply(&commit([("k1", 1000), ("k2", 2000)]))
+            .await
+            .unwrap();
+        server
+            .reply(&commit([("k3", 2000), ("k2", 2500)]))
+            .await
+            .unwrap();
+        // behind k1's committed offset
+        server
+            .reply(&commit([("k1", 500), ("k3", 2100)]))
+            .await
+            .unwrap();
 
         let msg = MessageBuilder::new()
-            .bodykind(BodyKind::ListCommittedOffsets)
-            .insert("keys", json!(vec!["k1", "k2"]))
+            .bodykind(BodyKind::ListCommittedOffsets {
+                keys: vec!["k1".to_string(), "k2".to_string()],
+            })
             .build();
-        let reply_msg = server.reply(&msg).await.unwrap();
-        assert_eq!(BodyKind::ListCommittedOffsetsOk, reply_msg.body.kind);
+        let reply_msg = server.reply(&msg).await.unwrap().unwrap();
+        let BodyKind::ListCommittedOffsetsOk {
+            offsets: ret_offsets,
+        } = reply_msg.body.kind
+        else {
+            panic!(
+                "expect list_committed_offsets_ok, got {:?}",
+                reply_msg.body.kind
+            );
+        };
 
-        let ret_offsets = reply_msg.body.payload.get_raw("offsets");
-        let ret_offsets: HashMap<String, usize> =
-            serde_json::from_value(ret_offsets.clone()).unwrap();
+        assert_eq!(2, ret_offsets.len());
         assert_eq!(1000, ret_offsets["k1"]);
         assert_eq!(2500, ret_offsets["k2"]);
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage() {
+        let nodes = vec![KafkaServer::new(StorageConfig::memory())];
+        let mut sim = Simulation::with_nodes(nodes, SimConfig::new(4)).await;
+        for i in 0..20 {
+            let kind = BodyKind::Send {
+                key: format!("k{}", i % 3),
+                msg: i,
+            };
+            assert!(sim.call("n0", kind).await.is_some());
+        }
+        let kind = BodyKind::Poll {
+            offsets: (0..3).map(|i| (format!("k{}", i), 2)).collect(),
+        };
+        let reply_msg = sim.call("n0", kind).await.unwrap();
+        let BodyKind::PollOk { msgs } = reply_msg.body.kind else {
+            panic!("expect poll_ok, got {:?}", reply_msg.body.kind);
+        };
+        assert_eq!(vec![[2, 6], [3, 9], [4, 12], [5, 15], [6, 18]], msgs["k0"]);
 
-        drop(server);
-        clean_disk_data().await;
+        let violations = kafka::check(sim.history());
+        assert!(violations.is_empty(), "{:?}", violations);
+    }
+
+    #[tokio::test]
+    async fn test_interval_fsync() {
+        let dir = TempDir::new();
+        let mut config = StorageConfig::new(dir.path());
+        config.segment.fsync = FsyncPolicy::Interval(Duration::from_secs(60));
+        let nodes = vec![KafkaServer::new(config)];
+        let mut sim = Simulation::with_nodes(nodes, SimConfig::new(5)).await;
+
+        let kind = BodyKind::Send {
+            key: "k".to_string(),
+            msg: 1,
+        };
+        assert!(sim.call("n0", kind).await.is_some());
+        let storage = sim.node("n0").storage.read().await;
+        assert_eq!(1, storage.as_ref().unwrap().unsynced());
+        drop(storage);
+
+        // no append follows, the periodic sync alone puts the record on the disk
+        sim.run_for(61_000).await;
+        let storage = sim.node("n0").storage.read().await;
+        assert_eq!(0, storage.as_ref().unwrap().unsynced());
     }
 }
@@ -0,0 +1,106 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use async_trait::async_trait;
+
+use super::{
+    memory::MemoryStore,
+    storage::{Backend, Storage, StorageConfig},
+};
+
+/**
+ * append-only logs, one per key, each numbering its msgs from offset 0
+ */
+#[async_trait]
+pub trait LogStore: Debug + Send + Sync {
+    /**
+     * append msg to key, return offset to this msg
+     */
+    async fn append(&mut self, key: &str, msg: usize) -> usize;
+
+    /**
+     * every (offset, msg) of each key from its offset on,
+     * keys without any are left out
+     */
+    async fn read_from(&self, offsets: &HashMap<String, usize>)
+        -> HashMap<String, Vec<[usize; 2]>>;
+
+    /**
+     * offset the next msg of key gets, 0 for a key never appended to
+     */
+    fn high_watermark(&self, key: &str) -> usize;
+
+    /**
+     * drop msgs of key before offset, offsets of the others don't change.
+     * a store may keep some of them, e.g. the disk only drops whole segments
+     */
+    async fn truncate(&mut self, key: &str, offset: usize);
+
+    /**
+     * wait until every appended msg is on the disk, nothing to do for a store without one
+     */
+    async fn sync(&mut self) {}
+
+    /**
+     * msgs appended but not synced to the disk yet
+     */
+    fn unsynced(&self) -> usize {
+        0
+    }
+}
+
+/**
+ * open the store of node_id with the backend in config
+ */
+pub async fn open(node_id: &str, config: &StorageConfig) -> Box<dyn LogStore> {
+    match config.backend {
+        Backend::Disk => Box::new(Storage::new(node_id, config).await),
+        Backend::Memory => Box::new(MemoryStore::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        kafka::storage::{Backend, StorageConfig},
+        utils::tests::TempDir,
+    };
+
+    use super::open;
+
+    #[tokio::test]
+    async fn test_backends_agree() {
+        let dir = TempDir::new();
+        let disk = StorageConfig::new(dir.path());
+        let memory = StorageConfig::memory();
+        assert_eq!(Backend::Memory, memory.backend);
+
+        for config in [disk, memory] {
+            let mut store = open("n0", &config).await;
+            assert_eq!(0, store.high_watermark("k1"));
+            for i in 0..5 {
+                assert_eq!(i, store.append("k1", 100 + i).await);
+            }
+            store.append("k2", 200).await;
+            assert_eq!(5, store.high_watermark("k1"));
+
+            let offsets = HashMap::from([
+                ("k1".to_string(), 3),
+                ("k2".to_string(), 1),
+                ("k3".to_string(), 0),
+            ]);
+            let msgs = store.read_from(&offsets).await;
+            assert_eq!(1, msgs.len(), "{:?}", config.backend);
+            assert_eq!(vec![[3, 103], [4, 104]], msgs["k1"]);
+
+            store.truncate("k1", 3).await;
+            assert_eq!(5, store.high_watermark("k1"));
+            let offsets = HashMap::from([("k1".to_string(), 3)]);
+            assert_eq!(
+                vec![[3, 103], [4, 104]],
+                store.read_from(&offsets).await["k1"]
+            );
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use super::log_store::LogStore;
+
+/**
+ * msgs of one key, the first one at offset `start`
+ */
+#[derive(Debug, Default)]
+struct Log {
+    start: usize,
+    msgs: Vec<usize>,
+}
+
+impl Log {
+    fn next_offset(&self) -> usize {
+        self.start + self.msgs.len()
+    }
+}
+
+/**
+ * logs kept in memory only, gone with the process.
+ * maelstrom doesn't need the log to outlive a node, and tests and the
+ * simulator run without touching the filesystem
+ */
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    logs: HashMap<String, Log>,
+}
+
+#[async_trait]
+impl LogStore for MemoryStore {
+    async fn append(&mut self, key: &str, msg: usize) -> usize {
+        let log = self.logs.entry(key.to_owned()).or_default();
+        log.msgs.push(msg);
+        log.next_offset() - 1
+    }
+
+    async fn read_from(
+        &self,
+        offsets: &HashMap<String, usize>,
+    ) -> HashMap<String, Vec<[usize; 2]>> {
+        offsets
+            .iter()
+            .filter_map(|(key, offset)| {
+                let log = self.logs.get(key)?;
+                let from = offset.saturating_sub(log.start);
+                let msgs: Vec<[usize; 2]> = log
+                    .msgs
+                    .iter()
+                    .enumerate()
+                    .skip(from)
+                    .map(|(i, msg)| [log.start + i, *msg])
+                    .collect();
+                (!msgs.is_empty()).then(|| (key.to_owned(), msgs))
+            })
+            .collect()
+    }
+
+    fn high_watermark(&self, key: &str) -> usize {
+        self.logs.get(key).map_or(0, Log::next_offset)
+    }
+
+    async fn truncate(&mut self, key: &str, offset: usize) {
+        let Some(log) = self.logs.get_mut(key) else {
+            return;
+        };
+        let count = offset.saturating_sub(log.start).min(log.msgs.len());
+        log.msgs.drain(..count);
+        log.start += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::kafka::log_store::LogStore;
+
+    use super::MemoryStore;
+
+    #[tokio::test]
+    async fn test_truncate() {
+        let mut store = MemoryStore::default();
+        for i in 0..5 {
+            store.append("k1", 100 + i).await;
+        }
+        store.truncate("k1", 3).await;
+        store.truncate("k1", 1).await;
+        store.truncate("k2", 1).await;
+
+        let offsets = HashMap::from([("k1".to_string(), 0)]);
+        assert_eq!(
+            vec![[3, 103], [4, 104]],
+            store.read_from(&offsets).await["k1"]
+        );
+        assert_eq!(5, store.append("k1", 105).await);
+
+        store.truncate("k1", 10).await;
+        assert_eq!(6, store.high_watermark("k1"));
+        assert!(store.read_from(&offsets).await.is_empty());
+        assert_eq!(6, store.append("k1", 106).await);
+    }
+}
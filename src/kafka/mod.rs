@@ -1,4 +1,12 @@
 pub mod kafka_server;
+pub mod log_store;
+pub mod memory;
+pub mod partition;
+pub mod record;
+pub mod segment;
 pub mod storage;
 
 pub use kafka_server::KafkaServer;
+pub use log_store::LogStore;
+pub use memory::MemoryStore;
+pub use storage::{Backend, Storage, StorageConfig};
@@ -0,0 +1,323 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use super::segment::{FsyncPolicy, Segment, SegmentConfig};
+
+/**
+ * name of the directory holding the partition of key.
+ * keys may hold any character, so they are hex encoded
+ */
+pub fn dir_name(key: &str) -> String {
+    key.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+/**
+ * key of the partition in the directory, none if it isn't a partition
+ */
+pub fn key_of(dir_name: &str) -> Option<String> {
+    if !dir_name.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..dir_name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(dir_name.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/**
+ * the log of one key, split into segments.
+ * only the last segment is appended to, it is rolled into a new one
+ * once it grows past `segment_bytes`, older segments are never written again
+ * and can be deleted on their own
+ */
+#[derive(Debug)]
+pub struct Partition {
+    dir: PathBuf,
+    config: SegmentConfig,
+    /// ascending by base offset, never empty
+    segments: Vec<Segment>,
+}
+
+impl Partition {
+    /**
+     * open the partition in dir, creating it if missing.
+     * where the next append goes is found from the records of the last segment
+     */
+    pub async fn open(dir: &Path, config: SegmentConfig) -> Self {
+        fs::create_dir_all(dir)
+            .await
+            .unwrap_or_else(|_| panic!("failed to create partition dir {:?}", dir));
+
+        let mut base_offsets = Vec::new();
+        let mut entries = fs::read_dir(dir)
+            .await
+            .unwrap_or_else(|_| panic!("failed to list partition dir {:?}", dir));
+        while let Some(entry) = entries.next_entry().await.expect("failed to list segments") {
+            let name = entry.file_name();
+            let base_offset = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".log"))
+                .and_then(|base_offset| base_offset.parse::<usize>().ok());
+            if let Some(base_offset) = base_offset {
+                base_offsets.push(base_offset);
+            }
+        }
+        base_offsets.sort();
+        if base_offsets.is_empty() {
+            base_offsets.push(0);
+        }
+
+        let mut segments = Vec::with_capacity(base_offsets.len());
+        for (i, base_offset) in base_offsets.iter().enumerate() {
+            let next_offset = base_offsets.get(i + 1).copied().unwrap_or(*base_offset);
+            segments.push(Segment::open(dir, *base_offset, next_offset).await);
+        }
+        // only the segment being appended to can be torn by a crash
+        if let Some(active) = segments.last_mut() {
+            active.recover().await;
+        }
+
+        Self {
+            dir: dir.to_path_buf(),
+            config,
+            segments,
+        }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn next_offset(&self) -> usize {
+        self.active().next_offset()
+    }
+
+    fn active(&self) -> &Segment {
+        self.segments.last().expect("a partition has a segment")
+    }
+
+    /**
+     * append msg to the last segment, rolling it first if it is full
+     */
+    pub async fn append(&mut self, msg: usize) -> usize {
+        let active = self.active();
+        if active.size() >= self.config.segment_bytes && !active.is_empty() {
+            let next_offset = active.next_offset();
+            if self.config.fsync != FsyncPolicy::Never {
+                self.segments.last_mut().unwrap().sync().await;
+            }
+            let segment = Segment::open(&self.dir, next_offset, next_offset).await;
+            self.segments.push(segment);
+        }
+
+        let config = self.config;
+        self.segments
+            .last_mut()
+            .expect("a partition has a segment")
+            .append(msg, &config)
+            .await
+    }
+
+    pub fn unsynced(&self) -> usize {
+        self.segments.iter().map(Segment::unsynced).sum()
+    }
+
+    /**
+     * sync every segment with records not on the disk yet
+     */
+    pub async fn sync(&mut self) {
+        for segment in self.segments.iter_mut().filter(|s| s.unsynced() > 0) {
+            segment.sync().await;
+        }
+    }
+
+    /**
+     * every (offset, msg) from offset on, starting at the segment holding it
+     */
+    pub async fn read_from(&self, offset: usize) -> Vec<[usize; 2]> {
+        let first = self
+            .segments
+            .partition_point(|segment| segment.base_offset() <= offset)
+            .saturating_sub(1);
+
+        let mut ret = Vec::new();
+        for segment in self.segments[first..].iter() {
+            ret.extend(segment.read_from(offset).await);
+        }
+        ret
+    }
+
+    /**
+     * delete the segments holding only records before offset, the last segment is kept.
+     * return the number of deleted segments
+     */
+    pub async fn delete_before(&mut self, offset: usize) -> usize {
+        let count = self.segments[..self.segments.len() - 1]
+            .iter()
+            .take_while(|segment| segment.next_offset() <= offset)
+            .count();
+        for segment in self.segments.drain(..count).collect::<Vec<_>>() {
+            segment.delete().await;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::fs;
+
+    use super::{dir_name, key_of, Partition};
+    use crate::{
+        kafka::{
+            record::{self, Record},
+            segment::{FsyncPolicy, SegmentConfig},
+        },
+        utils::tests::TempDir,
+    };
+
+    #[test]
+    fn test_dir_name() {
+        assert_eq!("6b31", dir_name("k1"));
+        assert_eq!(Some("a:b/c".to_string()), key_of(&dir_name("a:b/c")));
+        assert_eq!(None, key_of("6b3"));
+        assert_eq!(None, key_of("zz"));
+    }
+
+    #[tokio::test]
+    async fn test_roll_read_and_delete() {
+        let tmp = TempDir::new();
+        let dir = tmp.path().join("6b31");
+        let config = SegmentConfig {
+            segment_bytes: 64,
+            index_interval_bytes: 16,
+            ..Default::default()
+        };
+        let mut partition = Partition::open(&dir, config).await;
+        for i in 0..100 {
+            assert_eq!(i, partition.append(1000 + i).await);
+        }
+        let segments = partition.segments().len();
+        assert!(segments > 5);
+
+        let expected: Vec<[usize; 2]> = (37..100).map(|i| [i, 1000 + i]).collect();
+        assert_eq!(expected, partition.read_from(37).await);
+        assert!(partition.read_from(100).await.is_empty());
+
+        // reopened, segments and their indexes come back from disk
+        drop(partition);
+        let mut partition = Partition::open(&dir, config).await;
+        assert_eq!(segments, partition.segments().len());
+        assert_eq!(expected, partition.read_from(37).await);
+        assert_eq!(100, partition.append(1100).await);
+
+        let deleted = partition.delete_before(37).await;
+        assert!(deleted > 0);
+        assert_eq!(segments - deleted, partition.segments().len());
+        let base_offset = partition.segments()[0].base_offset();
+        assert!(base_offset <= 37);
+        assert_eq!(base_offset, partition.read_from(0).await[0][0]);
+    }
+
+    #[tokio::test]
+    async fn test_recover_torn_record() {
+        let tmp = TempDir::new();
+        let dir = tmp.path().join("6b31");
+        let mut partition = Partition::open(&dir, SegmentConfig::default()).await;
+        for i in 0..3 {
+            partition.append(i).await;
+        }
+        let size = partition.segments()[0].size();
+        drop(partition);
+
+        // a crash in the middle of the 4th append
+        let path = dir.join(format!("{:020}.log", 0));
+        let mut torn = Record { offset: 3, msg: 3 }.encode();
+        torn.truncate(10);
+        let mut bytes = fs::read(&path).await.unwrap();
+        bytes.extend(torn);
+        fs::write(&path, bytes).await.unwrap();
+
+        let mut partition = Partition::open(&dir, SegmentConfig::default()).await;
+        assert_eq!(size, partition.segments()[0].size());
+        assert_eq!(size, fs::metadata(&path).await.unwrap().len());
+        assert_eq!(3, partition.append(3).await);
+        let expected: Vec<[usize; 2]> = (0..4).map(|i| [i, i]).collect();
+        assert_eq!(expected, partition.read_from(0).await);
+    }
+
+    #[tokio::test]
+    async fn test_recover_index_past_log() {
+        let tmp = TempDir::new();
+        let dir = tmp.path().join("6b31");
+        let config = SegmentConfig {
+            index_interval_bytes: 16,
+            ..Default::default()
+        };
+        let mut partition = Partition::open(&dir, config).await;
+        for i in 0..10 {
+            partition.append(i).await;
+        }
+        drop(partition);
+
+        // power lost, the index made it to disk but the last 7 records didn't
+        let log_path = dir.join(format!("{:020}.log", 0));
+        let index_path = dir.join(format!("{:020}.index", 0));
+        let record_bytes = Record { offset: 0, msg: 0 }.encode().len() as u64;
+        let size = record::HEADER_BYTES + 3 * record_bytes;
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&log_path)
+            .await
+            .unwrap()
+            .set_len(size)
+            .await
+            .unwrap();
+        let mut index = fs::read(&index_path).await.unwrap();
+        index.extend(1000u64.to_be_bytes());
+        index.extend((1u64 << 20).to_be_bytes());
+        fs::write(&index_path, index).await.unwrap();
+
+        let mut partition = Partition::open(&dir, config).await;
+        assert_eq!(3, partition.next_offset());
+        assert_eq!(
+            2 * 16,
+            fs::metadata(&index_path).await.unwrap().len(),
+            "entries of offsets 1 and 2 are left"
+        );
+        for i in 3..6 {
+            assert_eq!(i, partition.append(100 + i).await);
+        }
+        drop(partition);
+
+        let partition = Partition::open(&dir, config).await;
+        let expected: Vec<[usize; 2]> = (0..3)
+            .map(|i| [i, i])
+            .chain((3..6).map(|i| [i, 100 + i]))
+            .collect();
+        assert_eq!(expected, partition.read_from(0).await);
+        assert_eq!(expected[4..], partition.read_from(4).await);
+    }
+
+    #[tokio::test]
+    async fn test_offsets_survive_kill() {
+        let tmp = TempDir::new();
+        let dir = tmp.path().join("6b31");
+        let config = SegmentConfig {
+            fsync: FsyncPolicy::Always,
+            ..Default::default()
+        };
+        let mut partition = Partition::open(&dir, config).await;
+        for i in 0..3 {
+            assert_eq!(i, partition.append(i).await);
+        }
+        // killed, nothing runs on the way out
+        std::mem::forget(partition);
+
+        let mut partition = Partition::open(&dir, config).await;
+        assert_eq!(3, partition.next_offset());
+        assert_eq!(3, partition.append(3).await);
+    }
+}
@@ -0,0 +1,180 @@
+/// first bytes of every segment file
+pub const MAGIC: [u8; 4] = *b"dskl";
+/// version of the record format, bumped on any change to it
+pub const VERSION: u8 = 1;
+/// magic followed by the version
+pub const HEADER_BYTES: u64 = MAGIC.len() as u64 + 1;
+
+/// length and crc in front of every payload
+const PREFIX_BYTES: usize = 8;
+/// the offset and msg of a version 1 record
+const PAYLOAD_BYTES: usize = 16;
+
+/**
+ * crc-32 (ieee) lookup table, one entry per byte value
+ */
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, b| {
+        (crc >> 8) ^ CRC_TABLE[((crc ^ *b as u32) & 0xff) as usize]
+    })
+}
+
+pub fn header() -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.push(VERSION);
+    header
+}
+
+/**
+ * check a segment starts with the header of this version
+ */
+pub fn check_header(bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() < HEADER_BYTES as usize || bytes[..MAGIC.len()] != MAGIC {
+        return Err("not a segment file".to_string());
+    }
+    match bytes[MAGIC.len()] {
+        VERSION => Ok(()),
+        version => Err(format!("unsupported record format version {}", version)),
+    }
+}
+
+/**
+ * one msg of a partition.
+ * on disk: payload length (u32), crc-32 of the payload (u32), then the payload,
+ * the offset (u64) and msg (u64), all big endian
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub offset: usize,
+    pub msg: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decoded {
+    /// a record and the bytes it took
+    Record(Record, usize),
+    /// the bytes end in the middle of a record, e.g. torn by a crash
+    Incomplete,
+    /// length or crc don't add up
+    Corrupt,
+}
+
+impl Record {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = [0; PAYLOAD_BYTES];
+        payload[..8].copy_from_slice(&(self.offset as u64).to_be_bytes());
+        payload[8..].copy_from_slice(&(self.msg as u64).to_be_bytes());
+
+        let mut bytes = Vec::with_capacity(PREFIX_BYTES + PAYLOAD_BYTES);
+        bytes.extend((PAYLOAD_BYTES as u32).to_be_bytes());
+        bytes.extend(crc32(&payload).to_be_bytes());
+        bytes.extend(payload);
+        bytes
+    }
+
+    /**
+     * decode the record at the start of bytes
+     */
+    pub fn decode(bytes: &[u8]) -> Decoded {
+        if bytes.len() < PREFIX_BYTES {
+            return Decoded::Incomplete;
+        }
+        let len = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+        if len != PAYLOAD_BYTES {
+            return Decoded::Corrupt;
+        }
+        let Some(payload) = bytes.get(PREFIX_BYTES..PREFIX_BYTES + len) else {
+            return Decoded::Incomplete;
+        };
+        if crc32(payload) != crc {
+            return Decoded::Corrupt;
+        }
+
+        let record = Record {
+            offset: u64::from_be_bytes(payload[..8].try_into().unwrap()) as usize,
+            msg: u64::from_be_bytes(payload[8..].try_into().unwrap()) as usize,
+        };
+        Decoded::Record(record, PREFIX_BYTES + len)
+    }
+
+    /**
+     * decode records from the start of bytes until they run out or break,
+     * return them with the number of bytes they took
+     */
+    pub fn decode_all(bytes: &[u8]) -> (Vec<Record>, usize) {
+        let mut records = Vec::new();
+        let mut position = 0;
+        while let Decoded::Record(record, len) = Record::decode(&bytes[position..]) {
+            records.push(record);
+            position += len;
+        }
+        (records, position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_header, crc32, header, Decoded, Record};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_header() {
+        assert_eq!(Ok(()), check_header(&header()));
+        assert!(check_header(b"0:k1:1").is_err());
+
+        let mut header = header();
+        header[4] = 9;
+        assert_eq!(
+            Err("unsupported record format version 9".to_string()),
+            check_header(&header)
+        );
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let a = Record { offset: 0, msg: 7 };
+        let b = Record {
+            offset: 1,
+            msg: usize::MAX,
+        };
+        let mut bytes = a.encode();
+        bytes.extend(b.encode());
+        assert_eq!(Decoded::Record(a, 24), Record::decode(&bytes));
+        assert_eq!((vec![a, b], 48), Record::decode_all(&bytes));
+
+        // torn in the middle of the second record
+        assert_eq!((vec![a], 24), Record::decode_all(&bytes[..40]));
+        assert_eq!(Decoded::Incomplete, Record::decode(&bytes[24..40]));
+
+        // a flipped bit fails the crc
+        bytes[30] ^= 1;
+        assert_eq!(Decoded::Corrupt, Record::decode(&bytes[24..]));
+        assert_eq!((vec![a], 24), Record::decode_all(&bytes));
+    }
+}
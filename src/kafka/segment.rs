@@ -0,0 +1,400 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    time::Instant,
+};
+
+use super::record::{self, Record, HEADER_BYTES};
+use crate::{log_warn, utils};
+
+/// bytes of one index entry, the offset and the position of its record
+const INDEX_ENTRY_BYTES: usize = 16;
+
+/**
+ * when appended records are synced to the disk.
+ * a killed process loses nothing written before it died, the os still
+ * has it, syncing only guards against losing the machine
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsyncPolicy {
+    /// sync after every append
+    Always,
+    /// sync unsynced records every this long, and on an append once this
+    /// long passed since the last sync
+    Interval(Duration),
+    /// leave it to the os
+    #[default]
+    Never,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    /**
+     * `always`, `never` or an interval in milliseconds
+     */
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(FsyncPolicy::Always),
+            "never" => Ok(FsyncPolicy::Never),
+            ms => ms
+                .parse()
+                .map(|ms| FsyncPolicy::Interval(Duration::from_millis(ms)))
+                .map_err(|_| format!("unknown fsync policy {}", s)),
+        }
+    }
+}
+
+/**
+ * sizes and durability of the segments of a partition
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentConfig {
+    /// a segment is rolled once it grows past this many bytes
+    pub segment_bytes: u64,
+    /// bytes of records between two entries of the sparse index
+    pub index_interval_bytes: u64,
+    pub fsync: FsyncPolicy,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        Self {
+            segment_bytes: 1 << 20,
+            index_interval_bytes: 4 << 10,
+            fsync: FsyncPolicy::default(),
+        }
+    }
+}
+
+/**
+ * a run of consecutive records of one partition, starting at `base_offset`.
+ * the records live in `<base_offset>.log` after a format header, each
+ * one length prefixed and checksummed, see `Record`.
+ * `<base_offset>.index` holds a sparse index, the position of a record
+ * every `index_interval_bytes`, so a read seeks close to its offset
+ * instead of scanning the segment from the start
+ */
+#[derive(Debug)]
+pub struct Segment {
+    base_offset: usize,
+    next_offset: usize,
+    log_path: PathBuf,
+    log: File,
+    index_file: File,
+    /// (offset, position) pairs, ascending
+    index: Vec<(usize, u64)>,
+    size: u64,
+    /// position of the last indexed record
+    last_indexed: u64,
+    last_sync: Instant,
+    /// records appended since the last sync
+    unsynced: usize,
+}
+
+impl Segment {
+    fn paths(dir: &Path, base_offset: usize) -> (PathBuf, PathBuf) {
+        (
+            dir.join(format!("{:020}.log", base_offset)),
+            dir.join(format!("{:020}.index", base_offset)),
+        )
+    }
+
+    /**
+     * open the segment starting at base_offset, creating its files if missing.
+     * next_offset is the offset the next record appended to it gets
+     */
+    pub async fn open(dir: &Path, base_offset: usize, next_offset: usize) -> Self {
+        let (log_path, index_path) = Self::paths(dir, base_offset);
+        let mut options = utils::rw_open_options();
+        options.append(true);
+
+        let mut log = options
+            .open(&log_path)
+            .await
+            .unwrap_or_else(|_| panic!("can't open segment {:?}", log_path));
+        let mut size = log.metadata().await.expect("failed to stat segment").len();
+        // a new segment, or one whose header was torn by a crash
+        if size < HEADER_BYTES {
+            log.set_len(0).await.expect("failed to truncate segment");
+            log.write_all(&record::header())
+                .await
+                .expect("failed to write segment header");
+            log.flush().await.expect("failed to flush segment header");
+            size = HEADER_BYTES;
+        } else {
+            let mut header = [0; HEADER_BYTES as usize];
+            let mut file = utils::r_open_options()
+                .open(&log_path)
+                .await
+                .unwrap_or_else(|_| panic!("cannot read segment {:?}", log_path));
+            let _ = file.read_exact(&mut header).await;
+            if let Err(err) = record::check_header(&header) {
+                panic!("can't open segment {:?}: {}", log_path, err);
+            }
+        }
+
+        let mut index_file = options
+            .open(&index_path)
+            .await
+            .unwrap_or_else(|_| panic!("can't open index {:?}", index_path));
+        let mut buffer = Vec::new();
+        index_file
+            .read_to_end(&mut buffer)
+            .await
+            .expect("failed to read index");
+        // a torn trailing entry is left out, and so are entries past the end
+        // of the log, whose records were lost while the index made it to disk
+        let index: Vec<(usize, u64)> = buffer
+            .chunks_exact(INDEX_ENTRY_BYTES)
+            .map(|entry| {
+                let offset = u64::from_be_bytes(entry[..8].try_into().unwrap());
+                let position = u64::from_be_bytes(entry[8..].try_into().unwrap());
+                (offset as usize, position)
+            })
+            .take_while(|(_, position)| *position < size)
+            .collect();
+        let index_len = (index.len() * INDEX_ENTRY_BYTES) as u64;
+        if index_len < buffer.len() as u64 {
+            log_warn!(
+                "",
+                "truncate index {:?} past the end of its segment, {} bytes",
+                index_path,
+                buffer.len() as u64 - index_len
+            );
+            index_file
+                .set_len(index_len)
+                .await
+                .expect("failed to truncate index");
+        }
+
+        Self {
+            base_offset,
+            next_offset: next_offset.max(base_offset),
+            log_path,
+            log,
+            index_file,
+            last_indexed: index
+                .last()
+                .map(|(_, position)| *position)
+                .unwrap_or(HEADER_BYTES),
+            index,
+            size,
+            last_sync: Instant::now(),
+            unsynced: 0,
+        }
+    }
+
+    pub fn base_offset(&self) -> usize {
+        self.base_offset
+    }
+
+    pub fn next_offset(&self) -> usize {
+        self.next_offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next_offset == self.base_offset
+    }
+
+    /**
+     * append msg as the record at `next_offset`, return its offset
+     */
+    pub async fn append(&mut self, msg: usize, config: &SegmentConfig) -> usize {
+        let offset = self.next_offset;
+        let position = self.size;
+        let record = Record { offset, msg }.encode();
+        self.log
+            .write_all(&record)
+            .await
+            .expect("failed to append record");
+        // tokio finishes writes in the background, wait so reads see the record
+        self.log.flush().await.expect("failed to flush record");
+
+        if position.saturating_sub(self.last_indexed) >= config.index_interval_bytes {
+            let mut entry = [0; INDEX_ENTRY_BYTES];
+            entry[..8].copy_from_slice(&(offset as u64).to_be_bytes());
+            entry[8..].copy_from_slice(&position.to_be_bytes());
+            self.index_file
+                .write_all(&entry)
+                .await
+                .expect("failed to append index entry");
+            self.index_file
+                .flush()
+                .await
+                .expect("failed to flush index entry");
+            self.index.push((offset, position));
+            self.last_indexed = position;
+        }
+
+        self.size += record.len() as u64;
+        self.next_offset += 1;
+        self.unsynced += 1;
+
+        match config.fsync {
+            FsyncPolicy::Always => self.sync().await,
+            FsyncPolicy::Interval(interval) if self.last_sync.elapsed() >= interval => {
+                self.sync().await
+            }
+            _ => {}
+        }
+        offset
+    }
+
+    /**
+     * wait until every appended record is on the disk
+     */
+    pub async fn sync(&mut self) {
+        self.log.sync_data().await.expect("failed to sync segment");
+        self.last_sync = Instant::now();
+        self.unsynced = 0;
+    }
+
+    /**
+     * records appended but not synced to the disk yet
+     */
+    pub fn unsynced(&self) -> usize {
+        self.unsynced
+    }
+
+    /**
+     * position to start scanning from for offset, the last indexed record before it
+     */
+    fn lookup(&self, offset: usize) -> u64 {
+        let i = self
+            .index
+            .partition_point(|(indexed, _)| *indexed <= offset);
+        match i {
+            0 => HEADER_BYTES,
+            i => self.index[i - 1].1,
+        }
+    }
+
+    /**
+     * the bytes of the segment from position to its end
+     */
+    async fn read_tail(&self, position: u64) -> Vec<u8> {
+        let mut file = utils::r_open_options()
+            .open(&self.log_path)
+            .await
+            .unwrap_or_else(|_| panic!("cannot read segment {:?}", self.log_path));
+        file.seek(SeekFrom::Start(position))
+            .await
+            .expect("failed to seek segment");
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)
+            .await
+            .expect("failed to read segment");
+        buffer
+    }
+
+    /**
+     * every (offset, msg) of the segment from offset on
+     */
+    pub async fn read_from(&self, offset: usize) -> Vec<[usize; 2]> {
+        if offset >= self.next_offset {
+            return Vec::new();
+        }
+
+        let position = self.lookup(offset);
+        let buffer = self.read_tail(position).await;
+        let (records, len) = Record::decode_all(&buffer);
+        if len < buffer.len() {
+            log_warn!(
+                "",
+                "corrupt record in {:?} at {}, skip the rest of the segment",
+                self.log_path,
+                position + len as u64
+            );
+        }
+
+        records
+            .into_iter()
+            .filter(|record| record.offset >= offset && record.offset < self.next_offset)
+            .map(|record| [record.offset, record.msg])
+            .collect()
+    }
+
+    /**
+     * find the next offset from the records on disk, and cut a torn or
+     * corrupt tail left by a crash in the middle of an append.
+     * only the tail after the last index entry is read, earlier records
+     * were complete when the segment moved past them.
+     * return the number of bytes cut
+     */
+    pub async fn recover(&mut self) -> u64 {
+        let (indexed, position) = self
+            .index
+            .last()
+            .copied()
+            .unwrap_or((self.base_offset, HEADER_BYTES));
+        let buffer = self.read_tail(position).await;
+        let (records, len) = Record::decode_all(&buffer);
+        // the indexed record itself may be the torn one
+        self.next_offset = records.last().map_or(indexed, |record| record.offset + 1);
+        if len == buffer.len() {
+            return 0;
+        }
+
+        let size = position + len as u64;
+        log_warn!(
+            "",
+            "truncate torn record in {:?} at {}, {} bytes",
+            self.log_path,
+            size,
+            buffer.len() - len
+        );
+        self.log
+            .set_len(size)
+            .await
+            .expect("failed to truncate segment");
+        self.size = size;
+        (buffer.len() - len) as u64
+    }
+
+    /**
+     * remove the files of the segment
+     */
+    pub async fn delete(self) {
+        let (log_path, index_path) = Self::paths(
+            self.log_path.parent().expect("segment has a dir"),
+            self.base_offset,
+        );
+        drop(self);
+        tokio::fs::remove_file(&log_path)
+            .await
+            .unwrap_or_else(|_| panic!("failed to delete segment {:?}", log_path));
+        tokio::fs::remove_file(&index_path)
+            .await
+            .unwrap_or_else(|_| panic!("failed to delete index {:?}", index_path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::FsyncPolicy;
+
+    #[test]
+    fn test_parse_fsync_policy() {
+        assert_eq!(Ok(FsyncPolicy::Always), "always".parse());
+        assert_eq!(Ok(FsyncPolicy::Never), "never".parse());
+        assert_eq!(
+            Ok(FsyncPolicy::Interval(Duration::from_millis(50))),
+            "50".parse()
+        );
+        assert!("sometimes".parse::<FsyncPolicy>().is_err());
+    }
+}
@@ -1,35 +1,137 @@
-use std::fs::OpenOptions as StdOpenOptions;
-use std::{collections::HashMap, io::Write};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use async_trait::async_trait;
 use tokio::fs;
-use tokio::{
-    fs::File,
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+
+use super::{
+    log_store::LogStore,
+    partition::{self, Partition},
+    segment::SegmentConfig,
 };
+use crate::log_warn;
+
+/// where the log is kept, `disk` (default) or `memory`
+const BACKEND_ENV: &str = "DIST_SYS_KAFKA_STORAGE";
+/// directory every node keeps its storage in, `log` by default
+const DIR_ENV: &str = "DIST_SYS_KAFKA_DIR";
+/// fsync policy of the log, `always`, `never` (default) or an interval in milliseconds
+const FSYNC_ENV: &str = "DIST_SYS_KAFKA_FSYNC";
+
+/**
+ * where the log is kept
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// segment files under `base_dir`, see `Storage`
+    #[default]
+    Disk,
+    /// nothing outlives the process, see `MemoryStore`
+    Memory,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disk" => Ok(Backend::Disk),
+            "memory" => Ok(Backend::Memory),
+            _ => Err(format!("unknown storage backend {}", s)),
+        }
+    }
+}
+
+/**
+ * where storage lives and, on disk, how its segments are written.
+ * maelstrom starts every node from the same directory, so each node
+ * gets its own directory under `base_dir`, named by its node id
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageConfig {
+    pub backend: Backend,
+    pub base_dir: PathBuf,
+    /// name of the directory holding the partitions, inside the node's directory
+    pub log_dir_name: String,
+    pub segment: SegmentConfig,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self::new("log")
+    }
+}
+
+impl StorageConfig {
+    pub fn new(base_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            backend: Backend::Disk,
+            base_dir: base_dir.into(),
+            log_dir_name: "kafka".to_string(),
+            segment: SegmentConfig::default(),
+        }
+    }
 
-use crate::utils;
+    /**
+     * keep the log in memory, the disk settings are left unused
+     */
+    pub fn memory() -> Self {
+        Self {
+            backend: Backend::Memory,
+            ..Default::default()
+        }
+    }
+
+    /**
+     * read from the `DIST_SYS_KAFKA_*` env vars
+     */
+    pub fn from_env() -> Self {
+        let mut config = match env::var(DIR_ENV) {
+            Ok(base_dir) => StorageConfig::new(base_dir),
+            Err(_) => StorageConfig::default(),
+        };
+        if let Ok(value) = env::var(BACKEND_ENV) {
+            match value.parse() {
+                Ok(backend) => config.backend = backend,
+                Err(err) => log_warn!("", "ignore {}={}: {}", BACKEND_ENV, value, err),
+            }
+        }
+        if let Ok(value) = env::var(FSYNC_ENV) {
+            match value.parse() {
+                Ok(fsync) => config.segment.fsync = fsync,
+                Err(err) => log_warn!("", "ignore {}={}: {}", FSYNC_ENV, value, err),
+            }
+        }
+        config
+    }
+
+    /**
+     * directory of the partitions of node_id
+     */
+    pub fn log_dir(&self, node_id: &str) -> PathBuf {
+        self.base_dir.join(node_id).join(&self.log_dir_name)
+    }
+}
 
 /**
  * storage logs & retrieve logs
  * append:
  *  incr offset by key & store logs
- * can have multiple instances
+ * every key has its own partition in the log dir of the node, split into segments
+ * with a sparse offset index, so a poll seeks close to its offset instead
+ * of scanning the history of all keys.
+ * offsets are rebuilt from the log when it is opened, nothing else is
+ * persisted, so a node killed at any point never hands out an offset twice
  */
 #[derive(Debug)]
 pub struct Storage {
-    log_name: String,
-    // store next offset to this key
+    dir: PathBuf,
+    config: SegmentConfig,
+    // store last offset of this key
     offsets: HashMap<String, usize>,
-    log: File,
-}
-
-impl Drop for Storage {
-    /**
-     * when drop, save offsets to meta file
-     */
-    fn drop(&mut self) {
-        self.write_meta();
-    }
+    partitions: HashMap<String, Partition>,
 }
 
 impl Storage {
@@ -37,56 +139,62 @@ impl Storage {
         &self.offsets
     }
 
-    pub fn log_name(&self) -> &str {
-        &self.log_name
+    pub fn dir(&self) -> &Path {
+        &self.dir
     }
 
-    pub async fn new() -> Self {
-        let open_options = utils::rw_open_options();
-        let filename = "kafka_log"; // TODO
-        fs::create_dir_all("log")
-            .await
-            .expect("failed to create log dir"); // TODO
-        let path = format!("log/{}", filename);
+    pub fn partition(&self, key: &str) -> Option<&Partition> {
+        self.partitions.get(key)
+    }
 
-        let log = open_options
-            .open(&path)
+    /**
+     * open the storage of node_id, creating its directory if missing
+     */
+    pub async fn new(node_id: &str, config: &StorageConfig) -> Self {
+        let dir = config.log_dir(node_id);
+        fs::create_dir_all(&dir)
+            .await
+            .unwrap_or_else(|_| panic!("failed to create log dir {:?}", dir));
+        let config = config.segment;
+        let mut offsets = HashMap::new();
+        let mut partitions = HashMap::new();
+        let mut entries = fs::read_dir(&dir).await.expect("failed to list log dir");
+        while let Some(entry) = entries
+            .next_entry()
             .await
-            .unwrap_or_else(|_| panic!("can't open log file {}", &path));
+            .expect("failed to list partitions")
+        {
+            let Some(key) = entry.file_name().to_str().and_then(partition::key_of) else {
+                continue;
+            };
+            let partition = Partition::open(&entry.path(), config).await;
+            if let Some(offset) = partition.next_offset().checked_sub(1) {
+                offsets.insert(key.clone(), offset);
+            }
+            partitions.insert(key, partition);
+        }
 
         Self {
-            offsets: Self::load_meta().await,
-            log_name: filename.to_string(),
-            log,
+            dir,
+            config,
+            offsets,
+            partitions,
         }
     }
 
     /**
      * append msg to key, return offset to this msg
      * start from zero
-     * with format: offset:key:msg
      */
     pub async fn append(&mut self, key: &str, msg: usize) -> usize {
-        let offset = match self.offsets.get_mut(key) {
-            Some(offset) => {
-                *offset += 1;
-                *offset
-            }
-            None => {
-                self.offsets.insert(key.to_owned(), 0);
-                0
-            }
-        };
-
-        self.log
-            .write_all(format!("{}:{}:{}", offset, key, msg).as_bytes())
-            .await
-            .expect("failed to append log");
-        self.log
-            .write_all("\n".as_bytes())
-            .await
-            .expect("failed to append newline");
+        if !self.partitions.contains_key(key) {
+            let dir = self.dir.join(partition::dir_name(key));
+            let partition = Partition::open(&dir, self.config).await;
+            self.partitions.insert(key.to_owned(), partition);
+        }
 
+        let offset = self.partitions.get_mut(key).unwrap().append(msg).await;
+        self.offsets.insert(key.to_owned(), offset);
         offset
     }
 
@@ -99,33 +207,13 @@ impl Storage {
     ) -> HashMap<String, Vec<[usize; 2]>> {
         let mut ret: HashMap<String, Vec<[usize; 2]>> = HashMap::with_capacity(offsets.len());
 
-        let file = utils::r_open_options()
-            .open(format!("log/{}", self.log_name))
-            .await
-            .expect("cannot read log file");
-
-        let reader = BufReader::new(file);
-        let mut lines = reader.lines();
-
-        while let Ok(Some(line)) = lines.next_line().await {
-            let arr = line.split(':').collect::<Vec<&str>>();
-            let offset = arr[0]
-                .parse::<usize>()
-                .expect("expect offset parse to usize");
-            let key = arr[1];
-            let msg = arr[2].parse::<usize>().unwrap();
-
-            if let Some(start_offset) = offsets.get(key) {
-                if start_offset <= &offset {
-                    let values = ret.get_mut(key);
-                    match values {
-                        Some(v) => v.push([offset, msg]),
-                        None => {
-                            let v = vec![[offset, msg]];
-                            ret.insert(key.to_owned(), v);
-                        }
-                    }
-                }
+        for (key, offset) in offsets.iter() {
+            let Some(partition) = self.partitions.get(key) else {
+                continue;
+            };
+            let msgs = partition.read_from(*offset).await;
+            if !msgs.is_empty() {
+                ret.insert(key.to_owned(), msgs);
             }
         }
 
@@ -133,41 +221,46 @@ impl Storage {
     }
 
     /**
-     * write meta data to disk
-     * TODO: use tokio library
+     * delete the segments of key holding only records before offset,
+     * return the number of deleted segments
      */
-    fn write_meta(&self) {
-        let mut options = StdOpenOptions::new();
-        options.read(true).write(true).truncate(true).create(true);
+    pub async fn delete_before(&mut self, key: &str, offset: usize) -> usize {
+        match self.partitions.get_mut(key) {
+            Some(partition) => partition.delete_before(offset).await,
+            None => 0,
+        }
+    }
+}
 
-        let mut meta = options.open(Self::meta_filename()).unwrap();
-        meta.write_all(serde_json::to_string(&self.offsets).unwrap().as_bytes())
-            .unwrap();
+#[async_trait]
+impl LogStore for Storage {
+    async fn append(&mut self, key: &str, msg: usize) -> usize {
+        Storage::append(self, key, msg).await
     }
 
-    /**
-     * load meta data into offsets
-     */
-    async fn load_meta() -> HashMap<String, usize> {
-        let options = utils::rw_open_options();
-        let meta = options
-            .open(Self::meta_filename())
-            .await
-            .expect("failed to open meta file");
-        let offsets = BufReader::new(meta)
-            .lines()
-            .next_line()
-            .await
-            .expect("failed to read meta data");
+    async fn read_from(
+        &self,
+        offsets: &HashMap<String, usize>,
+    ) -> HashMap<String, Vec<[usize; 2]>> {
+        Storage::read_from(self, offsets).await
+    }
+
+    fn high_watermark(&self, key: &str) -> usize {
+        self.offsets.get(key).map_or(0, |offset| offset + 1)
+    }
+
+    async fn truncate(&mut self, key: &str, offset: usize) {
+        self.delete_before(key, offset).await;
+    }
 
-        match offsets {
-            Some(str) => serde_json::from_str(&str).expect("failed to deserialized from meta data"),
-            None => HashMap::new(),
+    async fn sync(&mut self) {
+        for partition in self.partitions.values_mut() {
+            partition.sync().await;
         }
     }
 
-    fn meta_filename() -> &'static str {
-        "log/kafka_meta"
+    fn unsynced(&self) -> usize {
+        self.partitions.values().map(Partition::unsynced).sum()
     }
 }
 
@@ -175,18 +268,15 @@ impl Storage {
 pub mod tests {
     use std::collections::HashMap;
 
-    use crate::utils;
+    use crate::utils::tests::TempDir;
 
-    use super::Storage;
-
-    pub async fn clean_disk_data() {
-        utils::delete("log/kafka_log").await;
-        utils::delete("log/kafka_meta").await;
-    }
+    use super::{Backend, Storage, StorageConfig};
 
     #[tokio::test]
     async fn test_append_and_read() {
-        let mut storage = Storage::new().await;
+        let dir = TempDir::new();
+        let config = StorageConfig::new(dir.path());
+        let mut storage = Storage::new("n0", &config).await;
         storage.append("k1", 100).await;
         storage.append("k1", 101).await;
         storage.append("k2", 100).await;
@@ -207,7 +297,33 @@ pub mod tests {
         v.sort_by(|a, b| a[0].cmp(&b[0]));
         assert_eq!(&vec![[0, 100], [1, 101]], v);
 
-        drop(storage); // TODO
-        clean_disk_data().await;
+        // reopened, partitions and offsets come back from disk
+        drop(storage);
+        let storage = Storage::new("n0", &config).await;
+        assert_eq!(3, storage.offsets()["k1"]);
+        assert_eq!(1, storage.offsets()["k2"]);
+    }
+
+    #[tokio::test]
+    async fn test_nodes_isolated() {
+        let dir = TempDir::new();
+        let config = StorageConfig::new(dir.path());
+        let mut n0 = Storage::new("n0", &config).await;
+        let mut n1 = Storage::new("n1", &config).await;
+        assert_eq!(dir.path().join("n0").join("kafka"), n0.dir());
+
+        assert_eq!(0, n0.append("k1", 100).await);
+        assert_eq!(1, n0.append("k1", 101).await);
+        assert_eq!(0, n1.append("k1", 200).await);
+
+        let offsets = HashMap::from([("k1".to_string(), 0)]);
+        assert_eq!(vec![[0, 200]], n1.read_from(&offsets).await["k1"]);
+    }
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!(Ok(Backend::Disk), "disk".parse());
+        assert_eq!(Ok(Backend::Memory), "memory".parse());
+        assert!("tape".parse::<Backend>().is_err());
     }
 }
@@ -0,0 +1,202 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    error::{self, Error},
+    message::{BodyKind, Message},
+    server::ServerInner,
+};
+
+/// sequentially consistent kv service
+pub const SEQ_KV: &str = "seq-kv";
+/// linearizable kv service
+pub const LIN_KV: &str = "lin-kv";
+/// last-write-wins kv service
+pub const LWW_KV: &str = "lww-kv";
+
+/**
+ * client of a maelstrom kv service, requests are rpcs sent from the node.
+ * error replies come back as typed errors, e.g. `Error::KeyDoesNotExist`
+ * for a read of a missing key and `Error::PreconditionFailed` for a failed cas
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvClient {
+    service: String,
+}
+
+impl KvClient {
+    pub fn new(service: &str) -> Self {
+        Self {
+            service: service.to_string(),
+        }
+    }
+
+    pub fn seq() -> Self {
+        Self::new(SEQ_KV)
+    }
+
+    pub fn lin() -> Self {
+        Self::new(LIN_KV)
+    }
+
+    pub fn lww() -> Self {
+        Self::new(LWW_KV)
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub async fn read<K, V>(&self, node: &ServerInner, key: K) -> error::Result<V>
+    where
+        K: Serialize,
+        V: DeserializeOwned,
+    {
+        let kind = BodyKind::Read {
+            key: Some(to_value(key)?),
+            since: None,
+        };
+        let reply_msg = node.rpc(&self.service, kind).await?;
+        match reply_msg.body.kind {
+            // a key holding null comes back without a value
+            BodyKind::ReadOk { value, .. } => serde_json::from_value(value.unwrap_or(Value::Null))
+                .map_err(|err| {
+                    Error::MalformedRequest(format!("invalid value from {}: {}", self.service, err))
+                }),
+            _ => Err(self.unexpected(&reply_msg)),
+        }
+    }
+
+    pub async fn write<K, V>(&self, node: &ServerInner, key: K, value: V) -> error::Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let kind = BodyKind::Write {
+            key: to_value(key)?,
+            value: to_value(value)?,
+        };
+        let reply_msg = node.rpc(&self.service, kind).await?;
+        match reply_msg.body.kind {
+            BodyKind::WriteOk => Ok(()),
+            _ => Err(self.unexpected(&reply_msg)),
+        }
+    }
+
+    /**
+     * set key to `to` if it still holds `from`.
+     * with create_if_not_exists a missing key is created instead of failing
+     */
+    pub async fn cas<K, V>(
+        &self,
+        node: &ServerInner,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+    ) -> error::Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let kind = BodyKind::Cas {
+            key: to_value(key)?,
+            from: to_value(from)?,
+            to: to_value(to)?,
+            create_if_not_exists,
+        };
+        let reply_msg = node.rpc(&self.service, kind).await?;
+        match reply_msg.body.kind {
+            BodyKind::CasOk => Ok(()),
+            _ => Err(self.unexpected(&reply_msg)),
+        }
+    }
+
+    fn unexpected(&self, msg: &Message) -> Error {
+        Error::Crash(format!(
+            "unexpected reply from {}: {:?}",
+            self.service, msg.body.kind
+        ))
+    }
+}
+
+fn to_value<T: Serialize>(value: T) -> error::Result<Value> {
+    serde_json::to_value(value).map_err(|err| Error::MalformedRequest(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use crate::{
+        error::{self, Error},
+        harness::Cluster,
+        message::{BodyKind, Message},
+        server::{HasInner, Serve, ServerInner},
+    };
+
+    use super::{KvClient, LIN_KV, SEQ_KV};
+
+    #[derive(Debug, Default)]
+    struct Node {
+        inner: ServerInner,
+    }
+
+    impl HasInner for Node {
+        fn as_inner(&self) -> &ServerInner {
+            &self.inner
+        }
+    }
+
+    #[async_trait]
+    impl Serve for Node {
+        async fn reply(&self, msg: &Message) -> error::Result<Option<Message>> {
+            match &msg.body.kind {
+                BodyKind::Init { .. } => Ok(self.inner.init(msg)),
+                _ => Err(Error::NotSupported(format!("cannot handle msg: {:?}", msg))),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_write_cas() {
+        let cluster: Cluster<Node> = Cluster::new(2).await;
+        let (n0, n1) = (cluster.node("n0").as_inner(), cluster.node("n1").as_inner());
+        let kv = KvClient::seq();
+
+        let res = kv.read::<_, u64>(n0, "counter").await;
+        assert!(matches!(res, Err(Error::KeyDoesNotExist(_))));
+        let res = kv.cas(n0, "counter", 0, 1, false).await;
+        assert!(matches!(res, Err(Error::KeyDoesNotExist(_))));
+
+        kv.cas(n0, "counter", 0, 1, true).await.unwrap();
+        assert_eq!(1, kv.read::<_, u64>(n1, "counter").await.unwrap());
+
+        let res = kv.cas(n1, "counter", 0, 2, false).await;
+        assert!(matches!(res, Err(Error::PreconditionFailed(_))));
+        kv.cas(n1, "counter", 1, 2, false).await.unwrap();
+
+        kv.write(n0, "counter", 5).await.unwrap();
+        assert_eq!(Some(5.into()), cluster.kv(SEQ_KV).get("counter"));
+        assert_eq!(None, cluster.kv(LIN_KV).get("counter"));
+
+        // a key can be any json
+        kv.write(n0, [1, 2], vec!["a"]).await.unwrap();
+        let value: Vec<String> = kv.read(n1, [1, 2]).await.unwrap();
+        assert_eq!(vec!["a"], value);
+    }
+
+    #[tokio::test]
+    async fn test_read_null() {
+        let cluster: Cluster<Node> = Cluster::new(1).await;
+        let n0 = cluster.node("n0").as_inner();
+        let kv = KvClient::lin();
+
+        kv.write(n0, "empty", ()).await.unwrap();
+        kv.read::<_, ()>(n0, "empty").await.unwrap();
+        assert_eq!(None, kv.read::<_, Option<u64>>(n0, "empty").await.unwrap());
+
+        let res = kv.read::<_, u64>(n0, "empty").await;
+        assert!(matches!(res, Err(Error::MalformedRequest(_))));
+    }
+}
@@ -1,4 +1,15 @@
+pub mod checker;
+pub mod counter_server;
+pub mod crdt;
+pub mod error;
+pub mod harness;
 pub mod kafka;
+pub mod kv;
+pub mod logger;
 pub mod message;
+pub mod rpc;
 pub mod server;
+pub mod stats;
+pub mod timer;
+pub mod topology;
 pub mod utils;
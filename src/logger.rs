@@ -0,0 +1,220 @@
+use std::{
+    env, fmt,
+    io::Write,
+    str::FromStr,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+/// log level, `error`, `warn`, `info`, `debug` or `trace`
+pub const LOG_LEVEL_ENV: &str = "DIST_SYS_LOG";
+/// log format, `text` or `json`
+pub const LOG_FORMAT_ENV: &str = "DIST_SYS_LOG_FORMAT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            other => Err(format!("unknown log level: {}", other)),
+        }
+    }
+}
+
+/**
+ * writes one line per record to stderr, stdout belongs to the protocol.
+ * maelstrom passes no arguments to a node, so it is configured from the environment
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Logger {
+    pub level: Level,
+    pub json: bool,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self {
+            level: Level::Info,
+            json: false,
+        }
+    }
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/**
+ * the process wide logger, read from the environment on first use
+ */
+pub fn logger() -> &'static Logger {
+    LOGGER.get_or_init(Logger::from_env)
+}
+
+/**
+ * install a logger instead of reading the environment.
+ * return false if one is already in use
+ */
+pub fn init(logger: Logger) -> bool {
+    LOGGER.set(logger).is_ok()
+}
+
+impl Logger {
+    pub fn from_env() -> Self {
+        let mut logger = Logger::default();
+        if let Ok(level) = env::var(LOG_LEVEL_ENV) {
+            match level.parse() {
+                Ok(level) => logger.level = level,
+                Err(err) => eprintln!("{}, keep {}", err, logger.level.as_str()),
+            }
+        }
+        if let Ok(format) = env::var(LOG_FORMAT_ENV) {
+            logger.json = format.eq_ignore_ascii_case("json");
+        }
+        logger
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    pub fn log(&self, level: Level, node_id: &str, args: fmt::Arguments) {
+        if !self.enabled(level) {
+            return;
+        }
+
+        let line = self.format(timestamp_millis(), level, node_id, args);
+        let mut stderr = std::io::stderr().lock();
+        let _ = writeln!(stderr, "{}", line);
+    }
+
+    fn format(&self, ts: u128, level: Level, node_id: &str, args: fmt::Arguments) -> String {
+        if self.json {
+            json!({
+                "ts": ts,
+                "level": level.as_str(),
+                "node": node_id,
+                "msg": args.to_string(),
+            })
+            .to_string()
+        } else {
+            format!(
+                "{}.{:03} {:5} [{}] {}",
+                ts / 1000,
+                ts % 1000,
+                level.as_str().to_ascii_uppercase(),
+                node_id,
+                args
+            )
+        }
+    }
+}
+
+fn timestamp_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+/**
+ * `log!(level, node_id, "format", args..)`, the message is only formatted when enabled
+ */
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $node_id:expr, $($arg:tt)+) => {{
+        let logger = $crate::logger::logger();
+        if logger.enabled($level) {
+            logger.log($level, $node_id, format_args!($($arg)+));
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($node_id:expr, $($arg:tt)+) => { $crate::log!($crate::logger::Level::Error, $node_id, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($node_id:expr, $($arg:tt)+) => { $crate::log!($crate::logger::Level::Warn, $node_id, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($node_id:expr, $($arg:tt)+) => { $crate::log!($crate::logger::Level::Info, $node_id, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($node_id:expr, $($arg:tt)+) => { $crate::log!($crate::logger::Level::Debug, $node_id, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($node_id:expr, $($arg:tt)+) => { $crate::log!($crate::logger::Level::Trace, $node_id, $($arg)+) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Level, Logger};
+
+    #[test]
+    fn test_level() {
+        assert_eq!(Ok(Level::Debug), "DEBUG".parse());
+        assert!("verbose".parse::<Level>().is_err());
+
+        let logger = Logger::default();
+        assert!(logger.enabled(Level::Warn));
+        assert!(logger.enabled(Level::Info));
+        assert!(!logger.enabled(Level::Debug));
+    }
+
+    #[test]
+    fn test_format() {
+        let text = Logger::default().format(
+            1_700_000_000_042,
+            Level::Warn,
+            "n1",
+            format_args!("x={}", 1),
+        );
+        assert_eq!("1700000000.042 WARN  [n1] x=1", text);
+
+        let logger = Logger {
+            level: Level::Debug,
+            json: true,
+        };
+        let line = logger.format(7, Level::Debug, "n2", format_args!("hi"));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!("debug", value["level"]);
+        assert_eq!("n2", value["node"]);
+        assert_eq!("hi", value["msg"]);
+        assert_eq!(7, value["ts"]);
+    }
+}
@@ -3,7 +3,16 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+use crate::error::ErrorCode;
+
+/**
+ * maelstrom names its clients c0, c1, ... and its nodes n0, n1, ...
+ */
+pub fn is_client(id: &str) -> bool {
+    id.starts_with('c')
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct Message {
     pub src: String,
     #[serde(rename = "dest")]
@@ -18,7 +27,6 @@ pub struct MessageBuilder {
     pub bodykind: BodyKind,
     pub msg_id: usize,
     pub reply_to: Option<usize>,
-    pub payload: HashMap<String, Value>,
 }
 
 impl MessageBuilder {
@@ -45,11 +53,6 @@ impl MessageBuilder {
         self
     }
 
-    pub fn insert(mut self, key: &str, value: Value) -> Self {
-        self.payload.insert(key.to_string(), value);
-        self
-    }
-
     pub fn build(self) -> Message {
         Message {
             src: self.src,
@@ -58,78 +61,136 @@ impl MessageBuilder {
                 kind: self.bodykind,
                 msg_id: self.msg_id,
                 reply_to: self.reply_to,
-                payload: Payload(self.payload),
             },
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct Body {
-    #[serde(rename = "type")]
+    #[serde(flatten)]
     pub kind: BodyKind,
+    #[serde(default)]
     pub msg_id: usize,
     #[serde(rename = "in_reply_to", skip_serializing_if = "Option::is_none")]
     pub reply_to: Option<usize>,
-    #[serde(flatten)]
-    pub payload: Payload,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
-#[serde(rename_all = "snake_case")]
+/**
+ * message body, tagged by the "type" field.
+ * each variant carries the fields of that message type
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum BodyKind {
-    #[default]
-    Init,
+    Init {
+        node_id: String,
+        #[serde(default)]
+        node_ids: Vec<String>,
+    },
     InitOk,
-    Echo,
-    EchoOk,
+    Echo {
+        echo: String,
+    },
+    EchoOk {
+        echo: String,
+    },
     Generate,
-    GenerateOk,
-    Broadcast,
+    GenerateOk {
+        id: String,
+    },
+    Broadcast {
+        message: usize,
+    },
     BroadcastOk,
-    Read,
-    ReadOk,
-    Topology,
+    Read {
+        /// key to read from a kv service
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<Value>,
+        /// only return values added after this version of the store
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<usize>,
+    },
+    /// a broadcast read returns `messages`, a counter or kv read returns `value`
+    ReadOk {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        messages: Option<Vec<usize>>,
+        /// version of the store the values were read from, pass it as `since` for the next delta
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<Value>,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
     TopologyOk,
-    Send,
-    SendOk,
-    Poll,
-    PollOk,
-    CommitOffsets,
+    /// values pushed from one node to a neighbor, acknowledged by a `gossip_ok` echoing them
+    Gossip {
+        messages: Vec<usize>,
+    },
+    GossipOk {
+        messages: Vec<usize>,
+    },
+    Add {
+        delta: i64,
+    },
+    AddOk,
+    /// maelstrom kv services take any json as keys and values
+    Write {
+        key: Value,
+        value: Value,
+    },
+    WriteOk,
+    /// set key to `to` if it holds `from`, fails with `precondition-failed` otherwise
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        /// a missing key counts as holding `from`, instead of failing with `key-does-not-exist`
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+    /// the whole state of a crdt, merged into the receiver's own
+    Replicate {
+        state: Value,
+    },
+    Send {
+        key: String,
+        msg: usize,
+    },
+    SendOk {
+        offset: usize,
+    },
+    Poll {
+        offsets: HashMap<String, usize>,
+    },
+    PollOk {
+        msgs: HashMap<String, Vec<[usize; 2]>>,
+    },
+    CommitOffsets {
+        offsets: HashMap<String, usize>,
+    },
     CommitOffsetsOk,
-    ListCommittedOffsets,
-    ListCommittedOffsetsOk,
+    ListCommittedOffsets {
+        keys: Vec<String>,
+    },
+    ListCommittedOffsetsOk {
+        offsets: HashMap<String, usize>,
+    },
+    Error {
+        code: ErrorCode,
+        text: String,
+    },
 }
 
-// impl Default for BodyKind {
-//     fn default() -> Self {
-//         BodyKind::Init
-//     }
-// }
-
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Payload(HashMap<String, Value>);
-
-impl Payload {
-    pub fn init(key: &str, value: Value) -> Self {
-        let inner = HashMap::from([(key.to_string(), value)]);
-        Self(inner)
-    }
-
-    pub fn get_str(&self, key: &str) -> &str {
-        self.0[key].as_str().unwrap()
-    }
-
-    pub fn get_raw(&self, key: &str) -> &Value {
-        &self.0[key]
-    }
-
-    pub fn get_usize(&self, key: &str) -> usize {
-        self.0[key].as_u64().unwrap() as usize
-    }
-
-    pub fn put(&mut self, key: &str, value: Value) {
-        self.0.insert(key.to_string(), value);
+impl Default for BodyKind {
+    fn default() -> Self {
+        BodyKind::Init {
+            node_id: String::new(),
+            node_ids: Vec::new(),
+        }
     }
 }
 
@@ -137,7 +198,7 @@ impl Payload {
 mod tests {
     use std::assert_eq;
 
-    use crate::message::{BodyKind, Payload};
+    use crate::message::{BodyKind, Message};
 
     use super::{Body, MessageBuilder};
 
@@ -147,7 +208,6 @@ mod tests {
             kind: BodyKind::Generate,
             msg_id: 0,
             reply_to: None,
-            payload: Payload::default(),
         };
 
         let serialized_body = serde_json::to_string(&body).unwrap();
@@ -155,6 +215,24 @@ mod tests {
         assert_eq!(BodyKind::Generate, body.kind);
     }
 
+    #[test]
+    fn test_deserialize_by_type() {
+        let line =
+            r#"{"src":"c1","dest":"n1","body":{"type":"send","msg_id":3,"key":"k1","msg":123}}"#;
+        let msg: Message = serde_json::from_str(line).unwrap();
+        assert_eq!(3, msg.body.msg_id);
+        assert_eq!(
+            BodyKind::Send {
+                key: "k1".to_string(),
+                msg: 123
+            },
+            msg.body.kind
+        );
+
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1}}"#;
+        assert!(serde_json::from_str::<Message>(line).is_err());
+    }
+
     #[test]
     fn test_message_builder() {
         let msg = MessageBuilder::new()
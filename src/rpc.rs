@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    error::{self, Error},
+    message::{Body, BodyKind, Message},
+};
+
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct PendingCall {
+    dest: String,
+    tx: oneshot::Sender<Message>,
+}
+
+type PendingTable = Arc<Mutex<HashMap<usize, PendingCall>>>;
+
+/**
+ * removes a call from the pending table once its future is done or dropped,
+ * so a caller giving up before the reply or the timeout leaks nothing
+ */
+struct PendingGuard {
+    pending: PendingTable,
+    msg_id: usize,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.msg_id);
+    }
+}
+
+/**
+ * request/response on top of fire-and-forget messages.
+ * every call allocates a msg_id and waits in the pending table until a
+ * message with the same in_reply_to arrives, or the deadline passes
+ */
+#[derive(Debug, Clone)]
+pub struct Rpc {
+    outbox: mpsc::UnboundedSender<Message>,
+    pending: PendingTable,
+    next_id: Arc<AtomicUsize>,
+    timeout: Duration,
+}
+
+impl Rpc {
+    pub fn new(outbox: mpsc::UnboundedSender<Message>) -> Self {
+        Self {
+            outbox,
+            pending: Arc::default(),
+            next_id: Arc::new(AtomicUsize::new(1)),
+            timeout: DEFAULT_RPC_TIMEOUT,
+        }
+    }
+
+    /**
+     * unique and monotonically increasing, starting from 1.
+     * shared by every message the node sends, so replies never collide with calls
+     */
+    pub fn next_id(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /**
+     * number of calls still waiting for a reply
+     */
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /**
+     * fire-and-forget, msg is written out by the serve loop
+     */
+    pub fn send(&self, msg: Message) {
+        // the receiver lives as long as the serve loop, nothing to do once it is gone
+        let _ = self.outbox.send(msg);
+    }
+
+    /**
+     * send kind from src to dest and wait for the reply with the default timeout.
+     * an `error` reply resolves to the matching `Error`
+     */
+    pub fn call(
+        &self,
+        src: &str,
+        dest: &str,
+        kind: BodyKind,
+    ) -> impl Future<Output = error::Result<Message>> + Send + 'static {
+        self.call_with_timeout(src, dest, kind, self.timeout)
+    }
+
+    pub fn call_with_timeout(
+        &self,
+        src: &str,
+        dest: &str,
+        kind: BodyKind,
+        timeout: Duration,
+    ) -> impl Future<Output = error::Result<Message>> + Send + 'static {
+        let msg_id = self.next_id();
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(
+            msg_id,
+            PendingCall {
+                dest: dest.to_string(),
+                tx,
+            },
+        );
+
+        self.send(Message {
+            src: src.to_string(),
+            dst: dest.to_string(),
+            body: Body {
+                kind,
+                msg_id,
+                reply_to: None,
+            },
+        });
+
+        let guard = PendingGuard {
+            pending: self.pending.clone(),
+            msg_id,
+        };
+        let dest = dest.to_string();
+        async move {
+            let _guard = guard;
+            match tokio::time::timeout(timeout, rx).await {
+                Ok(Ok(reply_msg)) => match reply_msg.body.kind {
+                    BodyKind::Error { code, text } => Err(Error::new(code, text)),
+                    _ => Ok(reply_msg),
+                },
+                Ok(Err(_)) => Err(Error::Crash(format!("rpc {} to {} dropped", msg_id, dest))),
+                Err(_) => Err(Error::Timeout(format!(
+                    "rpc {} to {} timed out after {:?}",
+                    msg_id, dest, timeout
+                ))),
+            }
+        }
+    }
+
+    /**
+     * hand a reply to the call waiting for it.
+     * return false if msg does not answer any pending call
+     */
+    pub fn resolve(&self, msg: &Message) -> bool {
+        let Some(reply_to) = msg.body.reply_to else {
+            return false;
+        };
+
+        let mut pending = self.pending.lock().unwrap();
+        match pending.get(&reply_to) {
+            Some(call) if call.dest == msg.src => {
+                let call = pending.remove(&reply_to).unwrap();
+                // the caller may have given up already
+                let _ = call.tx.send(msg.clone());
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use crate::{
+        error::{Error, ErrorCode},
+        message::{BodyKind, Message, MessageBuilder},
+    };
+
+    use super::Rpc;
+
+    fn reply(request: &Message, kind: BodyKind) -> Message {
+        let mut msg = MessageBuilder::new()
+            .bodykind(kind)
+            .reply_to(request.body.msg_id)
+            .build();
+        msg.src = request.dst.clone();
+        msg.dst = request.src.clone();
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_call_resolved_by_reply() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let rpc = Rpc::new(tx);
+
+        let call = rpc.call("n1", "n2", BodyKind::Broadcast { message: 1 });
+        let request = rx.recv().await.unwrap();
+        assert_eq!("n2", request.dst);
+        assert_eq!(1, rpc.pending());
+
+        // a reply from another node does not match
+        let mut other = reply(&request, BodyKind::BroadcastOk);
+        other.src = "n3".to_string();
+        assert!(!rpc.resolve(&other));
+
+        assert!(rpc.resolve(&reply(&request, BodyKind::BroadcastOk)));
+        assert_eq!(BodyKind::BroadcastOk, call.await.unwrap().body.kind);
+        assert_eq!(0, rpc.pending());
+    }
+
+    #[tokio::test]
+    async fn test_call_error_reply() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let rpc = Rpc::new(tx);
+
+        let call = rpc.call(
+            "n1",
+            "n2",
+            BodyKind::Read {
+                key: None,
+                since: None,
+            },
+        );
+        let request = rx.recv().await.unwrap();
+        rpc.resolve(&reply(
+            &request,
+            BodyKind::Error {
+                code: ErrorCode::NotSupported,
+                text: "nope".to_string(),
+            },
+        ));
+
+        assert_eq!(Err(Error::NotSupported("nope".to_string())), call.await);
+    }
+
+    #[tokio::test]
+    async fn test_call_timeout() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut rpc = Rpc::new(tx);
+        rpc.set_timeout(Duration::from_millis(10));
+
+        let res = rpc
+            .call(
+                "n1",
+                "n2",
+                BodyKind::Read {
+                    key: None,
+                    since: None,
+                },
+            )
+            .await;
+        assert_eq!(ErrorCode::Timeout, res.unwrap_err().code());
+        assert_eq!(0, rpc.pending());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_call_not_pending() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let rpc = Rpc::new(tx);
+
+        let call = rpc.call("n1", "n2", BodyKind::Broadcast { message: 1 });
+        let request = rx.recv().await.unwrap();
+        assert_eq!(1, rpc.pending());
+
+        // never polled, e.g. lost a select!
+        drop(call);
+        assert_eq!(0, rpc.pending());
+        assert!(!rpc.resolve(&reply(&request, BodyKind::BroadcastOk)));
+    }
+}
@@ -1,101 +1,548 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
 use anyhow::Result;
 use async_trait::async_trait;
-use tokio::io::BufReader;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, Stdout};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::{mpsc, oneshot};
 
-use crate::message::{Body, BodyKind, Message, Payload};
+use crate::error::{self, Error, ErrorCode};
+use crate::message::{Body, BodyKind, Message};
+use crate::rpc::Rpc;
+use crate::stats::Stats;
+use crate::timer::Timers;
+use crate::{log_debug, log_info, log_warn};
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct ServerInner {
-    node_id: String,
-    next_msg_id: usize,
+    node_id: OnceLock<String>,
+    /// every node in the cluster including this one, in the order maelstrom sent them
+    node_ids: OnceLock<Vec<String>>,
+    rpc: Rpc,
+    /// receiving end of messages emitted by `send` and `rpc`, taken by the serve loop
+    outbox: Mutex<Option<mpsc::UnboundedReceiver<Message>>>,
+    timers: Timers,
+    /// set by the first init, so a repeated init schedules nothing twice
+    scheduled: AtomicBool,
+    stats: Arc<Stats>,
 }
 
-impl ServerInner {
-    fn advance(&mut self) {
-        self.next_msg_id += 1;
+impl Default for ServerInner {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            node_id: OnceLock::new(),
+            node_ids: OnceLock::new(),
+            rpc: Rpc::new(tx),
+            outbox: Mutex::new(Some(rx)),
+            timers: Timers::default(),
+            scheduled: AtomicBool::new(false),
+            stats: Arc::default(),
+        }
     }
+}
 
+impl ServerInner {
+    /**
+     * empty until the node is initialized
+     */
     pub fn node_id(&self) -> &str {
-        &self.node_id
+        self.node_id.get().map(String::as_str).unwrap_or_default()
     }
 
     // #[cfg(test)]
     // TODO mark only for test
-    pub fn set_node_id(&mut self, node_id: &str) {
-        self.node_id = node_id.to_string();
+    /**
+     * the node id can only be set once, later calls are ignored
+     */
+    pub fn set_node_id(&self, node_id: &str) {
+        let _ = self.node_id.set(node_id.to_string());
+    }
+
+    /**
+     * cluster membership, empty until the node is initialized
+     */
+    pub fn node_ids(&self) -> &[String] {
+        self.node_ids.get().map(Vec::as_slice).unwrap_or_default()
     }
 
-    pub fn next_msg_id(&self) -> usize {
-        self.next_msg_id
+    pub fn set_node_ids(&self, node_ids: &[String]) {
+        let _ = self.node_ids.set(node_ids.to_vec());
     }
 
-    pub fn init(&mut self, msg: &Message) -> Option<Message> {
-        self.node_id = msg.body.payload.get_str("node_id").to_string();
-        self.next_msg_id = 0;
+    /**
+     * all nodes except this one
+     */
+    pub fn peers(&self) -> Vec<&str> {
+        self.node_ids()
+            .iter()
+            .map(String::as_str)
+            .filter(|&id| id != self.node_id())
+            .collect()
+    }
+
+    /**
+     * position of this node in `node_ids`, the same on every node
+     */
+    pub fn node_index(&self) -> Option<usize> {
+        self.node_ids().iter().position(|id| id == self.node_id())
+    }
+
+    /**
+     * allocate the msg_id of an outbound message, never reused
+     */
+    pub fn next_id(&self) -> usize {
+        self.rpc.next_id()
+    }
+
+    pub fn init(&self, msg: &Message) -> Option<Message> {
+        if let BodyKind::Init { node_id, node_ids } = &msg.body.kind {
+            self.set_node_id(node_id);
+            self.set_node_ids(node_ids);
+        }
 
         let body = Body {
             kind: BodyKind::InitOk,
-            reply_to: Some(1),
-            msg_id: 1,
-            payload: Payload::default(),
+            msg_id: self.next_id(),
+            reply_to: Some(msg.body.msg_id),
         };
 
         Some(Message {
-            src: self.node_id.clone(),
+            src: self.node_id().to_string(),
             dst: msg.src.to_string(),
             body,
         })
     }
+
+    /**
+     * queue msg to be written out, without waiting for any reply
+     */
+    pub fn send(&self, msg: Message) {
+        self.rpc.send(msg);
+    }
+
+    /**
+     * send kind to dest and wait for its reply.
+     * the returned future doesn't borrow self, so it can be spawned
+     */
+    pub fn rpc(
+        &self,
+        dest: &str,
+        kind: BodyKind,
+    ) -> impl Future<Output = error::Result<Message>> + Send + 'static {
+        self.rpc.call(self.node_id(), dest, kind)
+    }
+
+    pub fn set_rpc_timeout(&mut self, timeout: Duration) {
+        self.rpc.set_timeout(timeout);
+    }
+
+    /**
+     * complete the rpc msg replies to, return false if nobody waits for it
+     */
+    pub fn resolve(&self, msg: &Message) -> bool {
+        self.rpc.resolve(msg)
+    }
+
+    /**
+     * periodic tasks and one-shot timers, they emit messages through `send`
+     */
+    /**
+     * true on the first call only, the node schedules its tasks then
+     */
+    pub fn first_schedule(&self) -> bool {
+        !self.scheduled.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn timers(&self) -> &Timers {
+        &self.timers
+    }
+
+    pub fn stats(&self) -> &Arc<Stats> {
+        &self.stats
+    }
+
+    /**
+     * parse one input line.
+     * a malformed line is counted, logged and, when its sender and msg_id can be
+     * recovered, answered with a `malformed-request` error
+     */
+    pub fn parse(&self, line: &str) -> Option<Message> {
+        match serde_json::from_str::<Message>(line) {
+            Ok(msg) => {
+                self.stats.record_received(&msg);
+                log_debug!(self.node_id(), "recv {}", line);
+                Some(msg)
+            }
+            Err(err) => {
+                self.stats.record_rejected();
+                log_warn!(self.node_id(), "reject malformed input {}: {}", line, err);
+                if let Some(reply_msg) = self.malformed(line, &err) {
+                    self.send(reply_msg);
+                }
+                None
+            }
+        }
+    }
+
+    fn malformed(&self, line: &str, err: &serde_json::Error) -> Option<Message> {
+        let value: Value = serde_json::from_str(line).ok()?;
+        let src = value.get("src")?.as_str()?;
+        let body = value.get("body")?;
+        // never answer a reply
+        if body.get("in_reply_to").is_some() {
+            return None;
+        }
+        let msg_id = body.get("msg_id")?.as_u64()? as usize;
+
+        let body = Body {
+            kind: BodyKind::Error {
+                code: ErrorCode::MalformedRequest,
+                text: err.to_string(),
+            },
+            msg_id: self.next_id(),
+            reply_to: Some(msg_id),
+        };
+
+        Some(Message {
+            src: self.node_id().to_string(),
+            dst: src.to_string(),
+            body,
+        })
+    }
+
+    pub fn take_outbox(&self) -> Option<mpsc::UnboundedReceiver<Message>> {
+        self.outbox.lock().unwrap().take()
+    }
+
+    /**
+     * build an `error` reply to msg
+     */
+    pub fn error(&self, msg: &Message, err: &Error) -> Message {
+        let body = Body {
+            kind: BodyKind::Error {
+                code: err.code(),
+                text: err.text().to_string(),
+            },
+            msg_id: self.next_id(),
+            reply_to: Some(msg.body.msg_id),
+        };
+
+        Message {
+            src: self.node_id().to_string(),
+            dst: msg.src.to_string(),
+            body,
+        }
+    }
 }
 
 pub trait HasInner {
-    fn as_inner(&mut self) -> &mut ServerInner;
+    fn as_inner(&self) -> &ServerInner;
 }
 
+/**
+ * a node served by a concurrent runtime:
+ *  - the input loop reads stdin and spawns one task per incoming message
+ *  - handlers run concurrently, each emits its messages into the outbox
+ *  - a writer task owns stdout and drains the outbox
+ *
+ * handlers only get `&self`, so servers keep mutable state behind locks:
+ * a `std::sync::Mutex` for in-memory state, which must never be held across
+ * an `.await`, and a `tokio::sync` lock for state used across I/O such as storage
+ */
 #[async_trait]
-pub trait Serve: HasInner {
-    async fn send(&mut self) -> Option<Vec<Message>> {
+pub trait Serve: HasInner + Send + Sync + 'static {
+    async fn send(&self) -> Option<Vec<Message>> {
         None
     }
 
-    async fn reply(&mut self, msg: &Message) -> Option<Message>;
+    async fn reply(&self, msg: &Message) -> error::Result<Option<Message>>;
+
+    /**
+     * called once the node is initialized, register periodic tasks and timers here
+     */
+    fn schedule(self: Arc<Self>) {}
 
-    async fn reply_inner(&mut self, msg: &Message) -> Option<Message> {
-        self.as_inner().advance();
-        self.reply(msg).await
+    /**
+     * reply to msg, a handler error becomes an `error` reply.
+     * replies (including errors) from other nodes are never answered
+     */
+    async fn handle(&self, msg: &Message) -> Option<Message> {
+        match self.reply(msg).await {
+            Ok(reply_msg) => reply_msg,
+            Err(err) => {
+                log_warn!(
+                    self.as_inner().node_id(),
+                    "failed to handle msg {} from {}: {}",
+                    msg.body.msg_id,
+                    msg.src,
+                    err
+                );
+                match msg.body.reply_to {
+                    Some(_) => None,
+                    None => Some(self.as_inner().error(msg, &err)),
+                }
+            }
+        }
+    }
+
+    /**
+     * handle one incoming message, everything it emits goes to the outbox
+     */
+    async fn process(self: Arc<Self>, msg: Message) {
+        if let Some(reply_msg) = self.handle(&msg).await {
+            self.as_inner().send(reply_msg);
+        }
+
+        // a duplicated init would register every periodic task twice
+        if matches!(msg.body.kind, BodyKind::Init { .. }) && self.as_inner().first_schedule() {
+            self.clone().schedule();
+            self.as_inner().timers().start();
+        }
+
+        if let Some(to_send) = self.send().await {
+            for to_send_msg in to_send {
+                self.as_inner().send(to_send_msg);
+            }
+        }
     }
 
     /// eventloop to process msg
-    async fn serve(&mut self) -> Result<()> {
-        let stdin = tokio::io::stdin();
-        let mut stdout = tokio::io::stdout();
-        let reader = BufReader::new(stdin);
+    async fn serve(self) -> Result<()>
+    where
+        Self: Sized,
+    {
+        let server = Arc::new(self);
+        let outbox = server
+            .as_inner()
+            .take_outbox()
+            .expect("outbox is taken, serve can only run once");
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let writer = tokio::spawn(write_to_stdout(
+            outbox,
+            server.as_inner().stats().clone(),
+            shutdown_rx,
+        ));
+
+        let reader = BufReader::new(tokio::io::stdin());
         let mut lines = reader.lines();
+        // every handler holds a clone, recv returns None once all of them are done
+        let (in_flight, mut all_done) = mpsc::channel::<()>(1);
 
         while let Some(line) = lines.next_line().await? {
-            let msg: Message = serde_json::from_str(&line).unwrap();
-            if let Some(reply_msg) = self.reply(&msg).await {
-                Self::write_to_stdout(&mut stdout, &reply_msg).await;
+            let Some(msg) = server.as_inner().parse(&line) else {
+                continue;
+            };
+            // replies to our rpc calls go to the waiting caller
+            if server.as_inner().resolve(&msg) {
+                continue;
             }
 
-            if let Some(to_send) = self.send().await {
-                for to_send_msg in to_send.iter() {
-                    self.as_inner().advance();
-                    Self::write_to_stdout(&mut stdout, to_send_msg).await;
+            let server = server.clone();
+            let in_flight = in_flight.clone();
+            tokio::spawn(async move {
+                server.process(msg).await;
+                drop(in_flight);
+            });
+        }
+
+        drop(in_flight);
+        let _ = all_done.recv().await;
+        let _ = shutdown_tx.send(());
+        writer.await?;
+        let stats = server.as_inner().stats().snapshot();
+        log_info!(
+            server.as_inner().node_id(),
+            "stats: {:?}, msgs-per-op: {:.2}",
+            stats,
+            stats.msgs_per_op()
+        );
+
+        Ok(())
+    }
+}
+
+/**
+ * the only writer of stdout, one json message per line.
+ * on shutdown, messages already queued are still written
+ */
+async fn write_to_stdout(
+    mut outbox: mpsc::UnboundedReceiver<Message>,
+    stats: Arc<Stats>,
+    mut shutdown: oneshot::Receiver<()>,
+) {
+    let mut output = BufWriter::new(tokio::io::stdout());
+
+    loop {
+        let msg = tokio::select! {
+            biased;
+            Some(msg) = outbox.recv() => msg,
+            _ = &mut shutdown => {
+                outbox.close();
+                while let Some(msg) = outbox.recv().await {
+                    write_line(&mut output, &stats, &msg).await;
                 }
+                output.flush().await.unwrap();
+                return;
             }
+        };
+
+        write_line(&mut output, &stats, &msg).await;
+        // batch whatever is already queued into one flush
+        while let Ok(msg) = outbox.try_recv() {
+            write_line(&mut output, &stats, &msg).await;
         }
+        output.flush().await.unwrap();
+    }
+}
 
-        Ok(())
+async fn write_line<W: AsyncWriteExt + Unpin>(output: &mut W, stats: &Stats, msg: &Message) {
+    let serialized = serde_json::to_string(&msg).unwrap();
+    log_debug!(&msg.src, "send {}", serialized);
+    output.write_all(serialized.as_bytes()).await.unwrap();
+    output.write_all(b"\n").await.unwrap();
+    stats.record_sent(msg);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use async_trait::async_trait;
+
+    use crate::{
+        error::{self, ErrorCode},
+        message::{BodyKind, Message, MessageBuilder},
+    };
+
+    use super::{HasInner, Serve, ServerInner};
+
+    #[derive(Debug, Default)]
+    struct Node {
+        inner: ServerInner,
+        scheduled: AtomicUsize,
+    }
+
+    impl HasInner for Node {
+        fn as_inner(&self) -> &ServerInner {
+            &self.inner
+        }
+    }
+
+    #[async_trait]
+    impl Serve for Node {
+        async fn reply(&self, msg: &Message) -> error::Result<Option<Message>> {
+            Ok(self.inner.init(msg))
+        }
+
+        fn schedule(self: Arc<Self>) {
+            self.scheduled.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_schedule_once() {
+        let node = Arc::new(Node::default());
+        let init = MessageBuilder::new()
+            .bodykind(BodyKind::Init {
+                node_id: "n0".to_string(),
+                node_ids: vec!["n0".to_string()],
+            })
+            .build();
+        node.clone().process(init.clone()).await;
+        node.clone().process(init).await;
+        assert_eq!(1, node.scheduled.load(Ordering::SeqCst));
+        node.inner.timers().stop();
+    }
+
+    #[test]
+    fn test_init_ok_replies_to_init() {
+        let inner = ServerInner::default();
+        let msg = MessageBuilder::new()
+            .bodykind(BodyKind::Init {
+                node_id: "n3".to_string(),
+                node_ids: vec!["n3".to_string()],
+            })
+            .msg_id(42)
+            .build();
+
+        let reply_msg = inner.init(&msg).unwrap();
+        assert_eq!("n3", reply_msg.src);
+        assert_eq!(BodyKind::InitOk, reply_msg.body.kind);
+        assert_eq!(Some(42), reply_msg.body.reply_to);
+        assert_eq!(1, reply_msg.body.msg_id);
+    }
+
+    #[test]
+    fn test_membership() {
+        let inner = ServerInner::default();
+        let msg = MessageBuilder::new()
+            .bodykind(BodyKind::Init {
+                node_id: "n2".to_string(),
+                node_ids: vec!["n1".to_string(), "n2".to_string(), "n3".to_string()],
+            })
+            .build();
+        inner.init(&msg);
+
+        assert_eq!(3, inner.node_ids().len());
+        assert_eq!(vec!["n1", "n3"], inner.peers());
+        assert_eq!(Some(1), inner.node_index());
+    }
+
+    #[tokio::test]
+    async fn test_parse_malformed() {
+        let inner = ServerInner::default();
+        let mut outbox = inner.take_outbox().unwrap();
+
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":5}}"#;
+        assert!(inner.parse(line).is_none());
+        let reply_msg = outbox.recv().await.unwrap();
+        assert_eq!("c1", reply_msg.dst);
+        assert_eq!(Some(5), reply_msg.body.reply_to);
+        assert!(matches!(
+            reply_msg.body.kind,
+            BodyKind::Error {
+                code: ErrorCode::MalformedRequest,
+                ..
+            }
+        ));
+
+        // nobody to answer
+        assert!(inner.parse("not json").is_none());
+        assert!(outbox.try_recv().is_err());
+
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":6}}"#;
+        assert_eq!(
+            BodyKind::Read {
+                key: None,
+                since: None,
+            },
+            inner.parse(line).unwrap().body.kind
+        );
+
+        let stats = inner.stats().snapshot();
+        assert_eq!(1, stats.received);
+        assert_eq!(2, stats.rejected);
     }
 
-    async fn write_to_stdout(output: &mut Stdout, msg: &Message) {
-        let serialized = serde_json::to_string(&msg).unwrap();
-        output.write_all(serialized.as_bytes()).await.unwrap();
-        output.write_all(b"\n").await.unwrap();
+    #[test]
+    fn test_next_id_is_unique() {
+        let inner = ServerInner::default();
+        let first = inner.next_id();
+        let _call = inner.rpc(
+            "n2",
+            BodyKind::Read {
+                key: None,
+                since: None,
+            },
+        );
 
-        // serde_json::to_writer(&mut *output, msg).unwrap();
-        // output.write_all(b"\n").unwrap();
+        assert_eq!(first + 2, inner.next_id());
     }
 }
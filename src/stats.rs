@@ -0,0 +1,92 @@
+use std::{
+    ops::Add,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde::Serialize;
+
+use crate::message::{is_client, Message};
+
+/**
+ * counters of a running node, safe to bump from any task
+ */
+#[derive(Debug, Default)]
+pub struct Stats {
+    received: AtomicUsize,
+    sent: AtomicUsize,
+    rejected: AtomicUsize,
+    client_requests: AtomicUsize,
+    peer_sent: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StatsSnapshot {
+    /// well-formed messages read from input
+    pub received: usize,
+    /// messages written to output
+    pub sent: usize,
+    /// input lines which could not be parsed as a message
+    pub rejected: usize,
+    /// received messages which came from a client
+    pub client_requests: usize,
+    /// sent messages which went to another node or service, not to a client
+    pub peer_sent: usize,
+}
+
+impl Stats {
+    pub fn record_received(&self, msg: &Message) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        if is_client(&msg.src) {
+            self.client_requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_sent(&self, msg: &Message) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        if !is_client(&msg.dst) {
+            self.peer_sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            received: self.received.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            client_requests: self.client_requests.load(Ordering::Relaxed),
+            peer_sent: self.peer_sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl StatsSnapshot {
+    /**
+     * messages between nodes per client request, the overhead budget of
+     * maelstrom's broadcast challenges. summed over all nodes it matches
+     * maelstrom's `msgs-per-op` for server messages
+     */
+    pub fn msgs_per_op(&self) -> f64 {
+        if self.client_requests == 0 {
+            return 0.0;
+        }
+        self.peer_sent as f64 / self.client_requests as f64
+    }
+}
+
+impl Add for StatsSnapshot {
+    type Output = StatsSnapshot;
+
+    fn add(self, other: StatsSnapshot) -> StatsSnapshot {
+        StatsSnapshot {
+            received: self.received + other.received,
+            sent: self.sent + other.sent,
+            rejected: self.rejected + other.rejected,
+            client_requests: self.client_requests + other.client_requests,
+            peer_sent: self.peer_sent + other.peer_sent,
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    task::JoinHandle,
+    time::{self, Instant, MissedTickBehavior},
+};
+
+pub type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+pub type TaskFn = Arc<dyn Fn() -> Task + Send + Sync>;
+
+/**
+ * a background task, either run every `period` or once after `delay`
+ */
+pub enum Timer {
+    Every { period: Duration, task: TaskFn },
+    After { delay: Duration, task: Task },
+}
+
+impl fmt::Debug for Timer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timer::Every { period, .. } => f.debug_struct("Every").field("period", period).finish(),
+            Timer::After { delay, .. } => f.debug_struct("After").field("delay", delay).finish(),
+        }
+    }
+}
+
+impl Timer {
+    /**
+     * run on the tokio runtime, a periodic task first fires one period from now.
+     * ticks missed while a task is slow are delayed instead of bursting
+     */
+    pub fn spawn(self) -> JoinHandle<()> {
+        match self {
+            Timer::Every { period, task } => tokio::spawn(async move {
+                let mut interval = time::interval_at(Instant::now() + period, period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    interval.tick().await;
+                    task().await;
+                }
+            }),
+            Timer::After { delay, task } => tokio::spawn(async move {
+                time::sleep(delay).await;
+                task.await;
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TimersState {
+    started: bool,
+    /// driven by the caller through `take_pending`, never spawned on tokio
+    manual: bool,
+    pending: Vec<Timer>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+/**
+ * timers registered by a node.
+ * they are queued until `start`, which the runtime calls once the node is initialized,
+ * and are spawned right away after that
+ */
+#[derive(Debug, Default)]
+pub struct Timers {
+    state: Mutex<TimersState>,
+}
+
+impl Timers {
+    pub fn every<F, Fut>(&self, period: Duration, f: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task: TaskFn = Arc::new(move || Box::pin(f()));
+        self.register(Timer::Every { period, task });
+    }
+
+    pub fn after<Fut>(&self, delay: Duration, fut: Fut)
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.register(Timer::After {
+            delay,
+            task: Box::pin(fut),
+        });
+    }
+
+    fn register(&self, timer: Timer) {
+        let mut state = self.state.lock().unwrap();
+        if state.started && !state.manual {
+            let handle = timer.spawn();
+            state.handles.retain(|h| !h.is_finished());
+            state.handles.push(handle);
+        } else {
+            state.pending.push(timer);
+        }
+    }
+
+    pub fn start(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.manual {
+            return;
+        }
+        state.started = true;
+        let pending: Vec<Timer> = state.pending.drain(..).collect();
+        for timer in pending {
+            state.handles.push(timer.spawn());
+        }
+    }
+
+    /**
+     * keep every timer queued, even after `start`, for a caller which drives them itself
+     */
+    pub fn set_manual(&self) {
+        self.state.lock().unwrap().manual = true;
+    }
+
+    /**
+     * hand the queued timers to a caller which drives them itself instead of tokio
+     */
+    pub fn take_pending(&self) -> Vec<Timer> {
+        self.state.lock().unwrap().pending.drain(..).collect()
+    }
+
+    /**
+     * abort every running timer, queued ones are kept
+     */
+    pub fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.started = false;
+        for handle in state.handles.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::{Timer, Timers};
+
+    #[tokio::test]
+    async fn test_every_and_after() {
+        let timers = Timers::default();
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let fired = Arc::new(AtomicUsize::new(0));
+
+        let counter = ticks.clone();
+        timers.every(Duration::from_millis(10), move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let counter = fired.clone();
+        timers.after(Duration::from_millis(10), async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        // nothing runs before start
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(0, ticks.load(Ordering::SeqCst));
+
+        timers.start();
+        tokio::time::sleep(Duration::from_millis(55)).await;
+        timers.stop();
+
+        let ticks_at_stop = ticks.load(Ordering::SeqCst);
+        assert!(ticks_at_stop >= 2, "ticks: {}", ticks_at_stop);
+        assert_eq!(1, fired.load(Ordering::SeqCst));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(ticks_at_stop, ticks.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_take_pending() {
+        let timers = Timers::default();
+        timers.every(Duration::from_millis(200), || async {});
+        timers.after(Duration::from_secs(1), async {});
+
+        let pending = timers.take_pending();
+        assert!(matches!(
+            pending[0],
+            Timer::Every { period, .. } if period == Duration::from_millis(200)
+        ));
+        assert!(matches!(pending[1], Timer::After { .. }));
+        assert!(timers.take_pending().is_empty());
+
+        timers.set_manual();
+        timers.start();
+        timers.after(Duration::from_millis(1), async {});
+        assert_eq!(1, timers.take_pending().len());
+    }
+}
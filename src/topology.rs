@@ -0,0 +1,467 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet, HashMap, VecDeque},
+    env, fmt,
+    hash::{Hash, Hasher},
+    iter,
+    sync::Arc,
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::log_warn;
+
+/// `given` (default), `star`, `spanning-tree`, `tree[:k]` or `random[:k]`
+pub const TOPOLOGY_ENV: &str = "DIST_SYS_TOPOLOGY";
+
+const DEFAULT_TREE_FANOUT: usize = 4;
+const DEFAULT_RANDOM_DEGREE: usize = 3;
+/// tries of the pairing model before `random` falls back to a circulant graph
+const RANDOM_ATTEMPTS: usize = 100;
+
+pub type Topology = HashMap<String, Vec<String>>;
+
+/**
+ * decides who talks to whom.
+ * every node computes the topology on its own, so it must only depend on
+ * the inputs, and links must go both ways for acks to come back
+ */
+pub trait TopologyStrategy: fmt::Debug + Send + Sync {
+    /**
+     * neighbors of every node, `given` is the topology sent by maelstrom
+     */
+    fn build(&self, node_ids: &[String], given: &Topology) -> Topology;
+}
+
+/**
+ * read the strategy from the environment, fall back to `given` if it is unset or invalid
+ */
+pub fn from_env() -> Arc<dyn TopologyStrategy> {
+    let Ok(value) = env::var(TOPOLOGY_ENV) else {
+        return Arc::new(Given);
+    };
+    parse(&value).unwrap_or_else(|err| {
+        log_warn!("", "ignore {}={}: {}, keep given", TOPOLOGY_ENV, value, err);
+        Arc::new(Given)
+    })
+}
+
+pub fn parse(s: &str) -> Result<Arc<dyn TopologyStrategy>, String> {
+    let (name, arg) = match s.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (s, None),
+    };
+    let k = |default: usize| -> Result<usize, String> {
+        match arg {
+            Some(arg) => match arg.parse() {
+                Ok(k) if k > 0 => Ok(k),
+                _ => Err(format!("invalid topology argument: {}", s)),
+            },
+            None => Ok(default),
+        }
+    };
+
+    match name.to_ascii_lowercase().as_str() {
+        "given" => Ok(Arc::new(Given)),
+        "star" => Ok(Arc::new(Star)),
+        "spanning-tree" => Ok(Arc::new(SpanningTree)),
+        "tree" => Ok(Arc::new(KaryTree {
+            k: k(DEFAULT_TREE_FANOUT)?,
+        })),
+        "random" => Ok(Arc::new(RandomRegular {
+            k: k(DEFAULT_RANDOM_DEGREE)?,
+        })),
+        _ => Err(format!("unknown topology: {}", s)),
+    }
+}
+
+/**
+ * the topology from maelstrom as is
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Given;
+
+impl TopologyStrategy for Given {
+    fn build(&self, _node_ids: &[String], given: &Topology) -> Topology {
+        given.clone()
+    }
+}
+
+/**
+ * the first node is a hub linked to all the others, two hops between any nodes
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Star;
+
+impl TopologyStrategy for Star {
+    fn build(&self, node_ids: &[String], _given: &Topology) -> Topology {
+        let mut links = Links::new(node_ids);
+        for i in 1..node_ids.len() {
+            links.link(0, i);
+        }
+        links.build(node_ids)
+    }
+}
+
+/**
+ * a breadth first tree over the given topology, rooted at the first node.
+ * nodes the given topology doesn't reach hang off the root
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpanningTree;
+
+impl TopologyStrategy for SpanningTree {
+    fn build(&self, node_ids: &[String], given: &Topology) -> Topology {
+        let mut links = Links::new(node_ids);
+        if node_ids.is_empty() {
+            return links.build(node_ids);
+        }
+
+        let mut visited = vec![false; node_ids.len()];
+        let mut queue = VecDeque::from([0]);
+        visited[0] = true;
+        while let Some(i) = queue.pop_front() {
+            let Some(others) = given.get(&node_ids[i]) else {
+                continue;
+            };
+            for other in others {
+                let Some(j) = links.index(other) else {
+                    continue;
+                };
+                if !visited[j] {
+                    visited[j] = true;
+                    links.link(i, j);
+                    queue.push_back(j);
+                }
+            }
+        }
+        for (j, _) in visited.iter().enumerate().filter(|(_, visited)| !**visited) {
+            links.link(0, j);
+        }
+        links.build(node_ids)
+    }
+}
+
+/**
+ * node i is the parent of nodes i*k+1 ..= i*k+k, depth is log_k(n)
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct KaryTree {
+    pub k: usize,
+}
+
+impl TopologyStrategy for KaryTree {
+    fn build(&self, node_ids: &[String], _given: &Topology) -> Topology {
+        let mut links = Links::new(node_ids);
+        for i in 1..node_ids.len() {
+            links.link((i - 1) / self.k, i);
+        }
+        links.build(node_ids)
+    }
+}
+
+/**
+ * a random k-regular graph, every node has exactly k neighbors
+ * (one node has k - 1 when n * k is odd, k is capped at n - 1).
+ * a shuffled ring keeps the overlay connected, so k is at least 2, the rest
+ * of the links come from the pairing model: every node gets a stub for each
+ * missing link and random pairs of stubs are linked, skipping pairs which would
+ * be a self loop or a duplicate. an attempt stuck with stubs no two of which fit
+ * is retried, if none works out the nodes are linked to the next k / 2 on the
+ * ring instead.
+ * the shuffles use a seed taken from the node ids
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct RandomRegular {
+    pub k: usize,
+}
+
+impl RandomRegular {
+    fn ring(node_ids: &[String], rng: &mut StdRng) -> (Links, Vec<usize>) {
+        let n = node_ids.len();
+        let mut ring: Vec<usize> = (0..n).collect();
+        ring.shuffle(rng);
+        let mut links = Links::new(node_ids);
+        for i in 0..n {
+            links.link(ring[i], ring[(i + 1) % n]);
+        }
+        (links, ring)
+    }
+
+    fn pairing(node_ids: &[String], k: usize, rng: &mut StdRng) -> Option<Links> {
+        let (mut links, _) = Self::ring(node_ids, rng);
+        let mut stubs: Vec<usize> = (0..node_ids.len())
+            .flat_map(|i| iter::repeat_n(i, k - links.degree(i)))
+            .collect();
+        if stubs.len() % 2 == 1 {
+            stubs.swap_remove(rng.gen_range(0..stubs.len()));
+        }
+        let fits = |links: &Links, a: usize, b: usize| a != b && !links.linked(a, b);
+        while stubs.len() > 1 {
+            let a = rng.gen_range(0..stubs.len());
+            let b = rng.gen_range(0..stubs.len());
+            if a == b || !fits(&links, stubs[a], stubs[b]) {
+                // stuck once no two stubs left can be paired
+                let any = (0..stubs.len())
+                    .any(|a| (a + 1..stubs.len()).any(|b| fits(&links, stubs[a], stubs[b])));
+                if !any {
+                    return None;
+                }
+                continue;
+            }
+            links.link(stubs[a], stubs[b]);
+            stubs.swap_remove(a.max(b));
+            stubs.swap_remove(a.min(b));
+        }
+        Some(links)
+    }
+
+    /**
+     * node ring[i] is linked to ring[i + 1 ..= i + k / 2], and to the opposite
+     * node for an odd k
+     */
+    fn circulant(node_ids: &[String], k: usize, rng: &mut StdRng) -> Links {
+        let n = node_ids.len();
+        let (mut links, ring) = Self::ring(node_ids, rng);
+        for i in 0..n {
+            for d in 2..=k / 2 {
+                links.link(ring[i], ring[(i + d) % n]);
+            }
+            if k % 2 == 1 && i < n / 2 {
+                links.link(ring[i], ring[i + n / 2]);
+            }
+        }
+        links
+    }
+}
+
+impl TopologyStrategy for RandomRegular {
+    fn build(&self, node_ids: &[String], _given: &Topology) -> Topology {
+        let n = node_ids.len();
+        if n < 2 {
+            return Links::new(node_ids).build(node_ids);
+        }
+        let k = self.k.max(2).min(n - 1);
+
+        // every node runs the same binary, so they all hash to the same seed
+        let mut hasher = DefaultHasher::new();
+        node_ids.hash(&mut hasher);
+        let mut rng = StdRng::seed_from_u64(hasher.finish());
+
+        let links = (0..RANDOM_ATTEMPTS)
+            .find_map(|_| Self::pairing(node_ids, k, &mut rng))
+            .unwrap_or_else(|| Self::circulant(node_ids, k, &mut rng));
+        links.build(node_ids)
+    }
+}
+
+/**
+ * undirected links between node indexes
+ */
+struct Links {
+    index: HashMap<String, usize>,
+    neighbors: Vec<BTreeSet<usize>>,
+}
+
+impl Links {
+    fn new(node_ids: &[String]) -> Self {
+        Self {
+            index: node_ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (id.clone(), i))
+                .collect(),
+            neighbors: vec![BTreeSet::new(); node_ids.len()],
+        }
+    }
+
+    fn index(&self, node_id: &str) -> Option<usize> {
+        self.index.get(node_id).copied()
+    }
+
+    fn degree(&self, i: usize) -> usize {
+        self.neighbors[i].len()
+    }
+
+    fn linked(&self, a: usize, b: usize) -> bool {
+        self.neighbors[a].contains(&b)
+    }
+
+    fn link(&mut self, a: usize, b: usize) {
+        if a != b {
+            self.neighbors[a].insert(b);
+            self.neighbors[b].insert(a);
+        }
+    }
+
+    fn build(self, node_ids: &[String]) -> Topology {
+        self.neighbors
+            .into_iter()
+            .enumerate()
+            .map(|(i, others)| {
+                let others = others.into_iter().map(|j| node_ids[j].clone()).collect();
+                (node_ids[i].clone(), others)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashSet, VecDeque};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{parse, RandomRegular, Topology};
+
+    fn node_ids(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("n{}", i)).collect()
+    }
+
+    /**
+     * maelstrom's default grid for 25 nodes
+     */
+    fn grid(node_ids: &[String]) -> Topology {
+        let mut topology = Topology::new();
+        for (i, node_id) in node_ids.iter().enumerate() {
+            let (row, col) = (i / 5, i % 5);
+            let mut others = Vec::new();
+            if row > 0 {
+                others.push(node_ids[i - 5].clone());
+            }
+            if row < 4 {
+                others.push(node_ids[i + 5].clone());
+            }
+            if col > 0 {
+                others.push(node_ids[i - 1].clone());
+            }
+            if col < 4 {
+                others.push(node_ids[i + 1].clone());
+            }
+            topology.insert(node_id.clone(), others);
+        }
+        topology
+    }
+
+    /**
+     * links go both ways and every node is reachable from the first one
+     */
+    fn assert_valid(node_ids: &[String], topology: &Topology) {
+        assert_eq!(node_ids.len(), topology.len());
+        for (node_id, others) in topology.iter() {
+            for other in others {
+                assert!(
+                    topology[other].contains(node_id),
+                    "{} -> {}",
+                    node_id,
+                    other
+                );
+            }
+        }
+
+        let mut seen = HashSet::from([&node_ids[0]]);
+        let mut queue = VecDeque::from([&node_ids[0]]);
+        while let Some(node_id) = queue.pop_front() {
+            for other in topology[node_id].iter() {
+                if seen.insert(other) {
+                    queue.push_back(other);
+                }
+            }
+        }
+        assert_eq!(node_ids.len(), seen.len());
+    }
+
+    fn build(strategy: &str, node_ids: &[String]) -> Topology {
+        parse(strategy).unwrap().build(node_ids, &grid(node_ids))
+    }
+
+    #[test]
+    fn test_strategies() {
+        let ids = node_ids(25);
+        for strategy in [
+            "given",
+            "star",
+            "spanning-tree",
+            "tree",
+            "tree:2",
+            "random:4",
+        ] {
+            let topology = build(strategy, &ids);
+            assert_valid(&ids, &topology);
+            assert_eq!(topology, build(strategy, &ids), "{}", strategy);
+        }
+
+        let star = build("star", &ids);
+        assert_eq!(24, star["n0"].len());
+        assert_eq!(vec!["n0"], star["n7"]);
+
+        // a tree has n - 1 links
+        for strategy in ["spanning-tree", "tree:3"] {
+            let links: usize = build(strategy, &ids).values().map(Vec::len).sum();
+            assert_eq!(2 * 24, links, "{}", strategy);
+        }
+        assert_eq!(vec!["n0", "n4", "n5", "n6"], build("tree:3", &ids)["n1"]);
+
+        let random = build("random:4", &ids);
+        assert!(random.values().all(|others| others.len() == 4));
+    }
+
+    #[test]
+    fn test_random_regular() {
+        for n in [2, 3, 4, 7, 10, 25] {
+            let ids = node_ids(n);
+            for k in 1..=6 {
+                let strategy = parse(&format!("random:{}", k)).unwrap();
+                let topology = strategy.build(&ids, &Topology::new());
+                assert_valid(&ids, &topology);
+
+                let k = k.max(2).min(n - 1);
+                let short = topology.values().filter(|others| others.len() != k);
+                if n * k % 2 == 0 {
+                    assert_eq!(0, short.count(), "n = {}, k = {}", n, k);
+                } else {
+                    let short: Vec<_> = short.collect();
+                    assert!(short.len() <= 1, "n = {}, k = {}", n, k);
+                    assert!(short.iter().all(|others| others.len() == k - 1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_circulant_regular() {
+        let ids = node_ids(10);
+        let mut rng = StdRng::seed_from_u64(1);
+        for k in 2..=9 {
+            let topology = RandomRegular::circulant(&ids, k, &mut rng).build(&ids);
+            assert_valid(&ids, &topology);
+            assert!(
+                topology.values().all(|others| others.len() == k),
+                "k = {}",
+                k
+            );
+        }
+    }
+
+    #[test]
+    fn test_spanning_tree_covers_unreachable() {
+        let ids = node_ids(4);
+        let given = Topology::from([
+            ("n0".to_string(), vec!["n1".to_string()]),
+            ("n1".to_string(), vec!["n0".to_string()]),
+        ]);
+        let topology = parse("spanning-tree").unwrap().build(&ids, &given);
+        assert_valid(&ids, &topology);
+        assert_eq!(vec!["n1", "n2", "n3"], topology["n0"]);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(parse("tree:0").is_err());
+        assert!(parse("random:x").is_err());
+        assert!(parse("ring").is_err());
+        assert_eq!(
+            "KaryTree { k: 8 }",
+            format!("{:?}", parse("Tree:8").unwrap())
+        );
+    }
+}
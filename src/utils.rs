@@ -23,16 +23,50 @@ pub async fn truncate(filename: &str) {
         .unwrap_or_else(|_| panic!("failed to truncate file: {}", filename));
 }
 
-pub async fn delete(filename: &str) {
-    tokio::fs::remove_file(filename)
-        .await
-        .unwrap_or_else(|_| panic!("failed to delete file: {}", filename));
-}
-
 #[cfg(test)]
 pub mod tests {
     use rand::Rng;
-    use std::iter;
+    use std::{
+        iter,
+        path::{Path, PathBuf},
+    };
+
+    /**
+     * a fresh directory under the system temp dir, removed when dropped
+     */
+    #[derive(Debug)]
+    pub struct TempDir {
+        path: PathBuf,
+    }
+
+    impl TempDir {
+        pub fn new() -> Self {
+            let name = format!(
+                "dist-sys-{}-{}",
+                std::process::id(),
+                rand::thread_rng().gen::<u64>()
+            );
+            let path = std::env::temp_dir().join(name);
+            std::fs::create_dir_all(&path).expect("failed to create temp dir");
+            Self { path }
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+    }
+
+    impl Default for TempDir {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
 
     pub fn generate_random_node_id() -> String {
         const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";